@@ -0,0 +1,34 @@
+use std::sync::mpsc;
+use std::thread;
+use log::{info,warn};
+use log::LevelFilter;
+use poly_logger::{CallbackLogger,ChannelLogger,LogEvent,PolyLogger,StderrLogger};
+
+// Feeds log events to a "log panel" thread over a channel, and to
+// an aggregation closure which counts warnings
+fn main() {
+    let (tx, rx) = mpsc::sync_channel::<LogEvent>(100);
+    let panel = thread::spawn(move || {
+        for event in rx {
+            println!("panel: {:<5} {} {:?}", event.level, event.message, event.key_values);
+        }
+    });
+
+    let mut pl = PolyLogger::new();
+    pl.add_named("panel", ChannelLogger::new(LevelFilter::Info, tx));
+    pl.add_named("count", CallbackLogger::new(LevelFilter::Warn, |event| {
+        eprintln!("aggregated a {} from {}", event.level, event.target);
+    }));
+    pl.add_named("stderr", StderrLogger::new(LevelFilter::Info));
+    let handle = pl.handle();
+    pl.init().unwrap();
+
+    for i in 0..3 {
+        info!(iteration = i; "This is INFO message {}", i);
+    }
+    warn!("This is a WARN message");
+
+    // Dropping the ChannelLogger closes the channel
+    handle.remove("panel");
+    panel.join().unwrap();
+}
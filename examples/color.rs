@@ -0,0 +1,33 @@
+use log::{trace,debug,info,warn,error};
+use log::LevelFilter;
+use poly_logger::{ColorMode,PolyLogger,StderrLogger};
+
+// Colors are only used on a terminal and if NO_COLOR is not set
+fn main() {
+    let mut tl0 = StderrLogger::new(LevelFilter::Trace);
+    tl0.color(ColorMode::Auto)
+       .timestamp_format("%T%.3f")
+       .msg_format("Level: [{timestamp}] {level:<5} - {args}");
+
+    let mut tl1 = StderrLogger::new(LevelFilter::Trace);
+    tl1.color(ColorMode::Auto)
+       .timestamp_format("%T%.3f")
+       .msg_format("Tags:  {color}[{timestamp}]{/color} {level} - {args}");
+
+    let mut tl2 = StderrLogger::new(LevelFilter::Trace);
+    tl2.color(ColorMode::Auto)
+       .color_line(true)
+       .msg_format("Line:  {level} - {args}");
+
+    let mut pl = PolyLogger::new();
+    pl.add(tl0);
+    pl.add(tl1);
+    pl.add(tl2);
+    pl.init().unwrap();
+
+    trace!("This is an TRACE message");
+    debug!("This is a DEBUG message");
+    info!("This is an INFO message");
+    warn!("This is a WARN message");
+    error!("This is an ERROR message");
+}
@@ -0,0 +1,31 @@
+use log::{debug,info,warn};
+use poly_logger::LoggerConfig;
+
+mod noisy {
+    pub fn chatter() {
+        log::info!("This INFO message is filtered out");
+        log::warn!("This WARN message gets through");
+    }
+}
+
+fn main() {
+    let path = std::env::args().nth(1)
+        .unwrap_or_else(|| "examples/config/logging.toml".to_string());
+
+    let config = match LoggerConfig::from_file(&path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Bad logging config: {}", e);
+            std::process::exit(1);
+        },
+    };
+    if let Err(e) = config.init() {
+        eprintln!("Bad logging config: {}", e);
+        std::process::exit(1);
+    }
+
+    debug!("This is a DEBUG message");
+    info!("This is an INFO message");
+    warn!("This is a WARN message");
+    noisy::chatter();
+}
@@ -0,0 +1,24 @@
+use std::time::Duration;
+use log::{debug,info,warn};
+use poly_logger::ConfigWatcher;
+
+// Edit the config file while this runs to see the changes applied
+fn main() {
+    let path = std::env::args().nth(1)
+        .unwrap_or_else(|| "examples/config/logging.toml".to_string());
+
+    let _watcher = match ConfigWatcher::init(&path, Duration::from_secs(1)) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            eprintln!("Bad logging config: {}", e);
+            std::process::exit(1);
+        },
+    };
+
+    for i in 0..60 {
+        debug!("This is DEBUG message {}", i);
+        info!("This is INFO message {}", i);
+        warn!("This is WARN message {}", i);
+        std::thread::sleep(Duration::from_secs(1));
+    }
+}
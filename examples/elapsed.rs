@@ -0,0 +1,19 @@
+use std::thread;
+use std::time::Duration;
+use log::info;
+use log::LevelFilter;
+use poly_logger::StderrLogger;
+
+// Profiles a startup sequence with monotonic relative times
+fn main() {
+    let mut logger = StderrLogger::new(LevelFilter::Info);
+    logger.msg_format("{uptime_ms:>6}ms {elapsed:>10}s (+{delta}s) {args}");
+    logger.init().unwrap();
+
+    info!("Starting");
+    thread::sleep(Duration::from_millis(150));
+    info!("Config loaded");
+    thread::sleep(Duration::from_millis(30));
+    info!("Database connected");
+    info!("Ready");
+}
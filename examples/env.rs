@@ -0,0 +1,19 @@
+use log::{trace,debug,info,warn,error};
+use log::LevelFilter;
+use poly_logger::StderrLogger;
+
+// Try e.g.
+// MYAPP_LOG_LEVEL=debug MYAPP_LOG_FORMAT="{level}: {args}" cargo run --example env
+fn main() {
+    let mut logger = StderrLogger::new(LevelFilter::Info);
+    logger.timestamp_format("%X%.6f")
+          .msg_format("[{timestamp} {file}:{line}] - {level} - {args}")
+          .env_overrides("MYAPP");
+    logger.init().unwrap();
+
+    trace!("This is an TRACE message");
+    debug!("This is a DEBUG message");
+    info!("This is an INFO message");
+    warn!("This is a WARN message");
+    error!("This is an ERROR message");
+}
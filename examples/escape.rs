@@ -0,0 +1,21 @@
+use log::{info,warn};
+use log::LevelFilter;
+use poly_logger::{mdc,Escape,GenLogger,PolyLogger};
+
+// Shows how each escaping policy writes hostile user input
+fn main() {
+    let mut pl = PolyLogger::new();
+    for (name, escape) in &[("none", Escape::None), ("escape", Escape::Escape),
+                            ("strip", Escape::Strip), ("indent", Escape::Indent)] {
+        let mut logger = GenLogger::new(LevelFilter::Info, std::io::stdout());
+        logger.escape(*escape)
+              .msg_format(&format!("{:<6} {{level}} {{args}} [{{mdc}}]", name));
+        pl.add_named(name, logger);
+    }
+    pl.init().unwrap();
+
+    let user = "dave\n[2020-01-01T00:00:00Z] INFO [src/main.rs:1] Forged login of admin";
+    let _user = mdc::scoped("user", user);
+    info!("Login failed for {}", user);
+    warn!("Terminal title \u{1b}]0;pwned\u{7}and \u{1b}[31mred\u{1b}[0m text\r\n");
+}
@@ -0,0 +1,30 @@
+use std::time::Duration;
+use log::{info,warn};
+use log::LevelFilter;
+use poly_logger::{HttpLogger,HttpPayload,PolyLogger,StderrLogger};
+
+// Posts to a local Loki instance, e.g. one started with
+// `docker run -p 3100:3100 grafana/loki`
+fn main() {
+    let mut http = HttpLogger::new(LevelFilter::Info,
+                                   "http://127.0.0.1:3100/loki/api/v1/push").unwrap();
+    http.payload(HttpPayload::Loki(vec![("app".to_string(), "http_example".to_string())]))
+        .gzip(true)
+        .batch_interval(Duration::from_millis(500));
+    let stats = http.stats();
+
+    let mut pl = PolyLogger::new();
+    pl.add_named("http", http);
+    pl.add_named("stderr", StderrLogger::new(LevelFilter::Info));
+    pl.init().unwrap();
+
+    for i in 0..10 {
+        info!(iteration = i; "This is INFO message {}", i);
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    warn!("This is a WARN message");
+
+    // Wait for the last batch to be sent
+    log::logger().flush();
+    eprintln!("Dropped {} records", stats.snapshot().dropped);
+}
@@ -0,0 +1,21 @@
+use log::{trace,debug,info,warn,error};
+use log::LevelFilter;
+use poly_logger::JournaldLogger;
+
+// Check the output with `journalctl -t journald -o verbose -n 5`
+fn main() {
+    let logger = match JournaldLogger::new(LevelFilter::Info) {
+        Ok(logger) => logger,
+        Err(e) => {
+            eprintln!("No systemd journal: {}", e);
+            std::process::exit(1);
+        },
+    };
+    logger.init().unwrap();
+
+    trace!("This is an TRACE message");
+    debug!("This is a DEBUG message");
+    info!(request_id = "abc123"; "This is an INFO message");
+    warn!("This is a WARN message\nwith a second line");
+    error!("This is an ERROR message");
+}
@@ -0,0 +1,29 @@
+use std::thread;
+use log::{info,warn};
+use log::LevelFilter;
+use poly_logger::{mdc,StderrLogger};
+
+// Tags every line logged while handling a request with its ID
+fn handle_request(id: u32) {
+    let _request = mdc::scoped("request_id", &format!("req-{}", id));
+    info!("Handling request");
+    if id.is_multiple_of(2) {
+        let _user = mdc::scoped("user", "dave");
+        warn!("Request for an even ID");
+    }
+    info!("Done");
+}
+
+fn main() {
+    let mut logger = StderrLogger::new(LevelFilter::Info);
+    logger.msg_format("{level} [{mdc.request_id}] {args} ({mdc})");
+    logger.init().unwrap();
+
+    let workers: Vec<_> = (0..3)
+        .map(|id| thread::spawn(move || handle_request(id)))
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    info!("No request in progress");
+}
@@ -0,0 +1,22 @@
+use log::{info,LevelFilter};
+use poly_logger::{FileLogger,PanicHook,PolyLogger,StderrLogger};
+
+// The panic is logged to both loggers, then printed by the default hook
+fn main() {
+    let mut file = FileLogger::new(LevelFilter::Info);
+    file.filename("panic.log")
+        .truncate(true);
+
+    let mut pl = PolyLogger::new();
+    pl.add(file.create());
+    pl.add(StderrLogger::new(LevelFilter::Info));
+    pl.init().unwrap();
+
+    let mut hook = PanicHook::new();
+    hook.backtrace(true);
+    hook.install();
+
+    info!("About to panic");
+    let widgets: Vec<u32> = Vec::new();
+    info!("The first widget is {}", widgets[0]);
+}
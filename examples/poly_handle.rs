@@ -0,0 +1,30 @@
+use log::{debug,info,warn};
+use log::LevelFilter;
+use poly_logger::{PolyLogger,StderrLogger};
+
+fn main() {
+    let mut console = StderrLogger::new(LevelFilter::Info);
+    console.msg_format("CONSOLE: {level} - {args}");
+
+    let mut pl = PolyLogger::new();
+    pl.add_named("console", console);
+    let handle = pl.handle();
+    pl.init().unwrap();
+
+    info!("This is an INFO message");
+    debug!("This DEBUG message is not logged");
+
+    // Attach a debug logger while logging continues
+    let mut debug_log = StderrLogger::new(LevelFilter::Debug);
+    debug_log.msg_format("DEBUG LOG: {level} - {args}");
+    handle.add("debug", debug_log);
+    eprintln!("------------------------------ {:?}", handle);
+    debug!("This DEBUG message goes to the debug logger");
+    warn!("This WARN message goes to both loggers");
+
+    // Detach it again
+    handle.remove("debug");
+    eprintln!("------------------------------ {:?}", handle);
+    debug!("This DEBUG message is not logged");
+    info!("This is an INFO message");
+}
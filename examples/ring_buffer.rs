@@ -0,0 +1,33 @@
+use log::{debug,error,info,trace};
+use log::{Level,LevelFilter};
+use poly_logger::{PolyLogger,RingBufferLogger,StderrLogger};
+
+// Trace records are only written to stderr when an error is logged,
+// or when the program panics
+fn main() {
+    let mut ring = RingBufferLogger::new(LevelFilter::Trace);
+    ring.msg_format("  [{timestamp}] {level} {args}")
+        .capacity(5)
+        .dump_on(Level::Error, std::io::stderr());
+    let handle = ring.handle();
+
+    std::panic::set_hook(Box::new(move |info| {
+        eprintln!("{}\nLast records:", info);
+        let _ = handle.dump(&mut std::io::stderr());
+    }));
+
+    let mut pl = PolyLogger::new();
+    pl.add(ring);
+    pl.add(StderrLogger::new(LevelFilter::Info));
+    pl.init().unwrap();
+
+    for i in 0..10 {
+        trace!("This is TRACE message {}", i);
+        debug!("This is DEBUG message {}", i);
+    }
+    info!("This is an INFO message");
+    error!("This ERROR dumps the last 5 records");
+
+    trace!("This TRACE message is shown by the panic hook");
+    panic!("Something went wrong");
+}
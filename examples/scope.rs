@@ -0,0 +1,26 @@
+use std::thread;
+use std::time::Duration;
+use log::{debug,info};
+use log::{Level,LevelFilter};
+use poly_logger::{scope,set_scope_level,StderrLogger};
+
+// Shows nested scopes as an indented outline, with timings
+fn main() {
+    let mut logger = StderrLogger::new(LevelFilter::Debug);
+    logger.indent(2)
+          .msg_format("{level:<5} {args}  [{scope}]");
+    logger.init().unwrap();
+    set_scope_level(Some(Level::Debug));
+
+    let _etl = scope("etl");
+    info!("Starting pipeline");
+    for table in &["customers", "orders"] {
+        let _load = scope(&format!("load_{}", table));
+        {
+            let _parse = scope("parse");
+            debug!("Parsing {}", table);
+            thread::sleep(Duration::from_millis(20));
+        }
+        info!("Loaded {}", table);
+    }
+}
@@ -0,0 +1,31 @@
+use std::time::Duration;
+use log::{debug,info};
+use log::LevelFilter;
+use poly_logger::{FileLogger,PolyLogger,StatsReporter,StderrLogger};
+
+// Reports what each logger wrote every second
+fn main() {
+    let console = StderrLogger::new(LevelFilter::Info);
+    let mut file = FileLogger::new(LevelFilter::Debug);
+    file.filename("stats.log")
+        .truncate(true);
+    let file = file.create();
+    let file_stats = file.stats();
+
+    let mut pl = PolyLogger::new();
+    pl.add_named("console", console);
+    pl.add_named("file", file);
+    let handle = pl.handle();
+    pl.init().unwrap();
+
+    let mut reporter = StatsReporter::new(Duration::from_secs(1));
+    reporter.add("stats.log", file_stats)
+            .poly(handle);
+    let _reporter = reporter.start();
+
+    for i in 0..25 {
+        info!("This is INFO message {}", i);
+        debug!("This is DEBUG message {}", i);
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
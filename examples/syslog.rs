@@ -0,0 +1,28 @@
+use log::{trace,debug,info,warn,error};
+use log::LevelFilter;
+use poly_logger::{Facility,PolyLogger,StderrLogger,SyslogLogger};
+
+// Logs to the local syslog daemon as well as stderr. Check the
+// output with e.g. `journalctl -t syslog -n 5` or /var/log/syslog
+fn main() {
+    let mut syslog = match SyslogLogger::unix(LevelFilter::Info, "/dev/log") {
+        Ok(logger) => logger,
+        Err(e) => {
+            eprintln!("No local syslog daemon: {}", e);
+            std::process::exit(1);
+        },
+    };
+    syslog.facility(Facility::Local0)
+          .msg_format("[{file}:{line}] {args}");
+
+    let mut pl = PolyLogger::new();
+    pl.add_named("syslog", syslog);
+    pl.add_named("stderr", StderrLogger::new(LevelFilter::Info));
+    pl.init().unwrap();
+
+    trace!("This is an TRACE message");
+    debug!("This is a DEBUG message");
+    info!(user = "dave"; "This is an INFO message");
+    warn!("This is a WARN message");
+    error!("This is an ERROR message");
+}
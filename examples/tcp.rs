@@ -0,0 +1,27 @@
+use std::time::Duration;
+use log::{info,warn};
+use log::LevelFilter;
+use poly_logger::{PolyLogger,StderrLogger,TcpLogger};
+
+// Start a collector with e.g. `nc -lk 5170` and stop and restart it
+// while this runs to see records buffered and replayed
+fn main() {
+    let mut tcp = TcpLogger::new(LevelFilter::Info, "127.0.0.1:5170").unwrap();
+    tcp.msg_format("[{timestamp}] {level} {args}")
+       .backoff(Duration::from_millis(250), Duration::from_secs(5))
+       .spool_file("/tmp/tcp_logger.spool");
+
+    let mut pl = PolyLogger::new();
+    pl.add_named("tcp", tcp);
+    pl.add_named("stderr", StderrLogger::new(LevelFilter::Warn));
+    pl.init().unwrap();
+
+    for i in 0..60 {
+        info!("This is INFO message {}", i);
+        if i % 10 == 0 {
+            warn!("This is WARN message {}", i);
+        }
+        std::thread::sleep(Duration::from_secs(1));
+    }
+    log::logger().flush();
+}
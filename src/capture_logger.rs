@@ -0,0 +1,276 @@
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex, Once};
+use log::{Level, LevelFilter, SetLoggerError};
+use super::log_formatter::LogFormatter;
+use super::log_event::LogEvent;
+use super::stats::{CountedLog, LoggerStats};
+use super::sys;
+
+/// Implements a logger which stores records in memory so tests can
+/// assert on what was logged
+///
+/// There are two ways to use it. A CaptureLogger created with new()
+/// keeps every record it is given, and its handle() returns a
+/// [Capture](struct.Capture.html) to query them. This suits a
+/// CaptureLogger added to a PolyLogger alongside other loggers.
+///
+/// As the log interface can only be initialized once per process,
+/// unit tests running in parallel threads instead call
+/// CaptureLogger::capture(). The first call installs a CaptureLogger
+/// at Trace level as the process logger, and each call returns a
+/// Capture which receives only the records logged on the calling
+/// thread until it is dropped. If a different logger was already
+/// installed, thread captures still work as long as it contains a
+/// CaptureLogger, e.g. as a PolyLogger child.
+///
+/// The rendered message uses the msg_format, which defaults to "{args}".
+///
+/// # Example
+/// ```
+/// use log::{info,warn,Level};
+/// use poly_logger::CaptureLogger;
+///
+/// let capture = CaptureLogger::capture();
+///
+/// info!(user = "dave"; "Logged in");
+/// warn!("Disk {}% full", 91);
+///
+/// capture.assert_logged(Level::Info, "Logged in");
+/// capture.assert_logged(Level::Warn, "91% full");
+/// capture.assert_not_logged(Level::Error, "");
+///
+/// let records = capture.records();
+/// assert_eq!(records.len(), 2);
+/// assert_eq!(records[0].key_values, vec![("user".to_string(), "dave".to_string())]);
+/// ```
+///
+pub struct CaptureLogger {
+    level_filter: LevelFilter,
+    log_formatter: LogFormatter,
+    records: Records,
+    // False for the logger installed by capture(), which
+    // only feeds thread captures
+    keep: bool,
+    stats: Arc<LoggerStats>,
+}
+
+/// A record stored by a [CaptureLogger](struct.CaptureLogger.html)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedRecord {
+    pub level: Level,
+    pub target: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    /// The message rendered with the msg_format
+    pub message: String,
+    pub key_values: Vec<(String, String)>,
+    /// The thread's [mdc](mdc/index.html) when the record was logged
+    pub mdc: Vec<(String, String)>,
+    /// The thread's [scopes](fn.scope.html), outermost first
+    pub scope: Vec<String>,
+}
+
+/// Records captured by a [CaptureLogger](struct.CaptureLogger.html),
+/// with methods to query and assert on them
+///
+/// A Capture returned by CaptureLogger::capture() stops capturing
+/// when dropped.
+pub struct Capture {
+    records: Records,
+    thread: bool,
+}
+
+type Records = Arc<Mutex<Vec<CapturedRecord>>>;
+
+thread_local! {
+    // The captures active on this thread, innermost last
+    static CAPTURES: RefCell<Vec<Records>> = const { RefCell::new(Vec::new()) };
+}
+
+impl CaptureLogger {
+    /// Instantiates a CaptureLogger
+    pub fn new(level_filter: LevelFilter) -> CaptureLogger {
+        let mut log_formatter = LogFormatter::new();
+        log_formatter.msg_format("{args}");
+        CaptureLogger {
+            level_filter,
+            log_formatter,
+            records: Arc::new(Mutex::new(Vec::new())),
+            keep: true,
+            stats: Arc::new(LoggerStats::new()),
+        }
+    }
+
+    /// Initializes the log interface using this CaptureLogger
+    /// as a boxed logger. This moves self so is the last
+    /// method to call on this object.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        log::set_max_level(self.level_filter);
+        log::set_boxed_logger(Box::new(self))
+    }
+
+    /// Sets the format of the rendered message.
+    /// See [GenLogger::msg_format](struct.GenLogger.html#method.msg_format)
+    pub fn msg_format(&mut self, format: &str) -> &mut Self {
+        self.log_formatter.msg_format(format);
+        self
+    }
+
+    /// Sets the timestamp format used by the {timestamp}
+    /// placeholder in the msg_format
+    pub fn timestamp_format(&mut self, format: &str) -> &mut Self {
+        self.log_formatter.timestamp_format(format);
+        self
+    }
+
+    /// Returns a Capture of every record this logger is given
+    pub fn handle(&self) -> Capture {
+        Capture { records: self.records.clone(), thread: false }
+    }
+
+    /// Returns the counts of records captured, with the length of
+    /// their rendered messages as bytes, and of records filtered out
+    /// by level. The stats are shared, so they can still be read
+    /// after this logger has been moved by init() or into a PolyLogger.
+    pub fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+
+    /// Starts capturing the records logged on this thread, installing
+    /// a CaptureLogger as the process logger on the first call
+    pub fn capture() -> Capture {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            // Fails if another logger is already installed
+            let mut logger = CaptureLogger::new(LevelFilter::Trace);
+            logger.keep = false;
+            let _ = logger.init();
+        });
+
+        let records = Arc::new(Mutex::new(Vec::new()));
+        CAPTURES.with(|captures| captures.borrow_mut().push(records.clone()));
+        Capture { records, thread: true }
+    }
+}
+
+impl Capture {
+    /// Returns a copy of the captured records, oldest first
+    pub fn records(&self) -> Vec<CapturedRecord> {
+        self.records.lock().unwrap().clone()
+    }
+
+    /// Returns the rendered messages, oldest first
+    pub fn messages(&self) -> Vec<String> {
+        self.records.lock().unwrap().iter().map(|r| r.message.clone()).collect()
+    }
+
+    /// Returns the number of captured records
+    pub fn len(&self) -> usize {
+        self.records.lock().unwrap().len()
+    }
+
+    /// Returns true if nothing was captured
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Discards the captured records
+    pub fn clear(&self) {
+        self.records.lock().unwrap().clear();
+    }
+
+    /// Returns true if a record was captured at the given
+    /// level with a message containing the given text
+    pub fn contains(&self, level: Level, text: &str) -> bool {
+        self.records.lock().unwrap().iter()
+            .any(|r| r.level == level && r.message.contains(text))
+    }
+
+    /// Panics unless a record was captured at the given level
+    /// with a message containing the given text
+    pub fn assert_logged(&self, level: Level, text: &str) {
+        if !self.contains(level, text) {
+            panic!("No {} record containing {:?} was logged. Captured:\n{}",
+                   level, text, self.dump());
+        }
+    }
+
+    /// Panics if a record was captured at the given level
+    /// with a message containing the given text
+    pub fn assert_not_logged(&self, level: Level, text: &str) {
+        if self.contains(level, text) {
+            panic!("A {} record containing {:?} was logged. Captured:\n{}",
+                   level, text, self.dump());
+        }
+    }
+
+    fn dump(&self) -> String {
+        self.records.lock().unwrap().iter()
+            .map(|r| format!("  {} {} {}\n", r.level, r.target, r.message))
+            .collect()
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        if !self.thread {
+            return;
+        }
+        // May run during thread teardown, after CAPTURES is gone
+        let _ = CAPTURES.try_with(|captures| {
+            captures.borrow_mut().retain(|c| !Arc::ptr_eq(c, &self.records));
+        });
+    }
+}
+
+impl log::Log for CaptureLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level_filter
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            self.stats.filtered();
+            return;
+        }
+
+        let _in_logger = sys::enter_logger();
+        let event = LogEvent::from(record);
+        let message = match self.log_formatter.event_msg(&event) {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("Invalid log format: {}", e);
+                self.stats.format_error();
+                event.message.clone()
+            },
+        };
+        self.stats.written(event.level, message.len());
+        let captured = CapturedRecord {
+            level: event.level,
+            target: event.target,
+            file: event.file,
+            line: event.line,
+            message,
+            key_values: event.key_values,
+            mdc: event.mdc,
+            scope: event.scope,
+        };
+
+        let _ = CAPTURES.try_with(|captures| {
+            for records in captures.borrow().iter() {
+                records.lock().unwrap().push(captured.clone());
+            }
+        });
+        if self.keep {
+            self.records.lock().unwrap().push(captured);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+impl CountedLog for CaptureLogger {
+    fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+}
@@ -0,0 +1,263 @@
+use std::cell::Cell;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Sender, SyncSender, TrySendError};
+use log::{LevelFilter, SetLoggerError};
+use super::log_event::LogEvent;
+use super::stats::{CountedLog, LoggerStats};
+use super::sys::{self, FlagGuard};
+
+/// Implements a logger which sends a [LogEvent](struct.LogEvent.html)
+/// for each record over a channel
+///
+/// Any [EventSender](trait.EventSender.html) may be used, which includes
+/// std::sync::mpsc Sender and SyncSender and, with the "crossbeam"
+/// feature, crossbeam_channel::Sender. Bounded channels are never
+/// waited on: events which do not fit, or which have no receiver,
+/// are dropped and counted.
+///
+/// # Example
+/// ```
+/// use std::sync::mpsc;
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::ChannelLogger;
+///
+/// let (tx, rx) = mpsc::channel();
+/// let logger = ChannelLogger::new(LevelFilter::Info, tx);
+///
+/// logger.log(&Record::builder()
+///     .args(format_args!("Hello {}", "channel"))
+///     .level(Level::Warn)
+///     .target("gui")
+///     .build());
+///
+/// let event = rx.recv().unwrap();
+/// assert_eq!(event.level, Level::Warn);
+/// assert_eq!(event.target, "gui");
+/// assert_eq!(event.message, "Hello channel");
+/// ```
+///
+pub struct ChannelLogger<S: EventSender> {
+    level_filter: LevelFilter,
+    sender: Mutex<S>,
+    dropped: Arc<AtomicU64>,
+    stats: Arc<LoggerStats>,
+}
+
+/// A channel sender which a [ChannelLogger](struct.ChannelLogger.html)
+/// can send events with
+pub trait EventSender: Send + 'static {
+    /// Sends an event without blocking, returning
+    /// false if it could not be sent
+    fn send_event(&self, event: LogEvent) -> bool;
+}
+
+impl EventSender for Sender<LogEvent> {
+    fn send_event(&self, event: LogEvent) -> bool {
+        self.send(event).is_ok()
+    }
+}
+
+impl EventSender for SyncSender<LogEvent> {
+    fn send_event(&self, event: LogEvent) -> bool {
+        match self.try_send(event) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+#[cfg(feature = "crossbeam")]
+impl EventSender for crossbeam_channel::Sender<LogEvent> {
+    fn send_event(&self, event: LogEvent) -> bool {
+        self.try_send(event).is_ok()
+    }
+}
+
+impl<S: EventSender> ChannelLogger<S> {
+    /// Instantiates a ChannelLogger which sends with the given sender
+    pub fn new(level_filter: LevelFilter, sender: S) -> ChannelLogger<S> {
+        ChannelLogger {
+            level_filter,
+            sender: Mutex::new(sender),
+            dropped: Arc::new(AtomicU64::new(0)),
+            stats: Arc::new(LoggerStats::new()),
+        }
+    }
+
+    /// Initializes the log interface using this ChannelLogger
+    /// as a boxed logger. This moves self so is the last
+    /// method to call on this object.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        log::set_max_level(self.level_filter);
+        log::set_boxed_logger(Box::new(self))
+    }
+
+    /// Returns the count of events which could not be sent.
+    /// The counter is shared, so it can still be read after
+    /// this logger has been moved by init() or into a PolyLogger.
+    pub fn dropped(&self) -> Arc<AtomicU64> {
+        self.dropped.clone()
+    }
+
+    /// Returns the counts of events sent, with the length of their
+    /// messages as bytes, and of events filtered or dropped. The
+    /// stats are shared, so they can still be read after this
+    /// logger has been moved by init() or into a PolyLogger.
+    pub fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+}
+
+impl<S: EventSender> log::Log for ChannelLogger<S> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level_filter
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            self.stats.filtered();
+            return;
+        }
+
+        let _in_logger = sys::enter_logger();
+        let event = LogEvent::from(record);
+        let bytes = event.message.len();
+        if self.sender.lock().unwrap().send_event(event) {
+            self.stats.written(record.level(), bytes);
+        } else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            self.stats.dropped(1);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+impl<S: EventSender> CountedLog for ChannelLogger<S> {
+    fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+}
+
+/// Implements a logger which calls a closure with a
+/// [LogEvent](struct.LogEvent.html) for each record
+///
+/// The closure runs in the thread doing the logging, so should be
+/// quick. Anything it logs itself is ignored to avoid recursion.
+///
+/// # Example
+/// ```
+/// use std::sync::{Arc,Mutex};
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::CallbackLogger;
+///
+/// let panel = Arc::new(Mutex::new(Vec::new()));
+/// let lines = panel.clone();
+/// let logger = CallbackLogger::new(LevelFilter::Info, move |event| {
+///     lines.lock().unwrap().push(format!("{} {}", event.level, event.message));
+/// });
+///
+/// logger.log(&Record::builder()
+///     .args(format_args!("Saved"))
+///     .level(Level::Info)
+///     .build());
+/// assert_eq!(*panel.lock().unwrap(), vec!["INFO Saved"]);
+/// ```
+///
+/// A callback which panics does not stop later records
+/// reaching it
+/// ```
+/// use std::sync::atomic::{AtomicUsize,Ordering};
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::CallbackLogger;
+///
+/// static CALLS: AtomicUsize = AtomicUsize::new(0);
+/// let logger = CallbackLogger::new(LevelFilter::Info, |event| {
+///     CALLS.fetch_add(1, Ordering::Relaxed);
+///     if event.message == "boom" {
+///         panic!("callback failed");
+///     }
+/// });
+/// let log = |msg: &str| logger.log(&Record::builder()
+///     .args(format_args!("{}", msg))
+///     .level(Level::Info)
+///     .build());
+///
+/// let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| log("boom")));
+/// assert!(result.is_err());
+/// log("after");
+/// assert_eq!(CALLS.load(Ordering::Relaxed), 2);
+/// ```
+///
+pub struct CallbackLogger {
+    level_filter: LevelFilter,
+    callback: Box<dyn Fn(LogEvent) + Send + Sync>,
+    stats: Arc<LoggerStats>,
+}
+
+thread_local! {
+    static IN_CALLBACK: Cell<bool> = const { Cell::new(false) };
+}
+
+impl CallbackLogger {
+    /// Instantiates a CallbackLogger which calls the given closure
+    pub fn new<F>(level_filter: LevelFilter, callback: F) -> CallbackLogger
+    where F: Fn(LogEvent) + Send + Sync + 'static {
+        CallbackLogger {
+            level_filter,
+            callback: Box::new(callback),
+            stats: Arc::new(LoggerStats::new()),
+        }
+    }
+
+    /// Initializes the log interface using this CallbackLogger
+    /// as a boxed logger. This moves self so is the last
+    /// method to call on this object.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        log::set_max_level(self.level_filter);
+        log::set_boxed_logger(Box::new(self))
+    }
+
+    /// Returns the counts of events passed to the callback, with the
+    /// length of their messages as bytes, of records filtered out by
+    /// level and of records logged by the callback itself, which are
+    /// dropped. The stats are shared, so they can still be read after
+    /// this logger has been moved by init() or into a PolyLogger.
+    pub fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+}
+
+impl log::Log for CallbackLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level_filter
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            self.stats.filtered();
+            return;
+        }
+
+        let _in_logger = sys::enter_logger();
+        // Cleared on drop, in case the callback panics
+        match FlagGuard::set(&IN_CALLBACK) {
+            Some(_guard) => {
+                let event = LogEvent::from(record);
+                let bytes = event.message.len();
+                (self.callback)(event);
+                self.stats.written(record.level(), bytes);
+            },
+            None => self.stats.dropped(1),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+impl CountedLog for CallbackLogger {
+    fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+}
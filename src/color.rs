@@ -0,0 +1,88 @@
+use log::Level;
+use serde::Deserialize;
+
+/// When to colorize the output of a
+/// [GenLogger](struct.GenLogger.html)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    /// Colorize if the writer is a terminal and the
+    /// NO_COLOR environment variable is not set
+    Auto,
+    /// Always colorize
+    Always,
+    /// Never colorize
+    Never,
+}
+
+impl ColorMode {
+    // Resolves the mode for a writer which may or may not be a terminal
+    pub(crate) fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => {
+                let no_color = std::env::var_os("NO_COLOR")
+                    .map(|v| !v.is_empty())
+                    .unwrap_or(false);
+                is_terminal && !no_color
+            },
+        }
+    }
+}
+
+/// The ANSI SGR codes used for each log level, such
+/// as "31" for red or "1;33" for bold yellow.
+///
+/// # Example
+/// ```
+/// use log::LevelFilter;
+/// use poly_logger::{ColorMode,ColorPalette,StderrLogger};
+///
+/// let mut palette = ColorPalette::default();
+/// palette.info = "1;36".to_string();
+///
+/// let mut logger = StderrLogger::new(LevelFilter::Info);
+/// logger.color(ColorMode::Auto)
+///       .color_palette(palette)
+///       .msg_format("{color}[{timestamp}]{/color} {level} {args}");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColorPalette {
+    pub error: String,
+    pub warn: String,
+    pub info: String,
+    pub debug: String,
+    pub trace: String,
+}
+
+impl Default for ColorPalette {
+    fn default() -> Self {
+        ColorPalette {
+            error: "1;31".to_string(),
+            warn: "33".to_string(),
+            info: "32".to_string(),
+            debug: "36".to_string(),
+            trace: "90".to_string(),
+        }
+    }
+}
+
+impl ColorPalette {
+    /// Returns the escape sequence which starts the color for a level
+    pub fn start(&self, level: Level) -> String {
+        let code = match level {
+            Level::Error => &self.error,
+            Level::Warn => &self.warn,
+            Level::Info => &self.info,
+            Level::Debug => &self.debug,
+            Level::Trace => &self.trace,
+        };
+        format!("\x1b[{}m", code)
+    }
+
+    /// Returns the escape sequence which resets the color
+    pub fn reset(&self) -> &'static str {
+        "\x1b[0m"
+    }
+}
@@ -0,0 +1,415 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use log::{LevelFilter, SetLoggerError};
+use serde::Deserialize;
+use super::gen_logger::GenLogger;
+use super::instance::{StdoutLogger, StderrLogger, FileLogger};
+use super::log_formatter::{LogFormatter, LogFormat, Escape};
+use super::color::ColorMode;
+use super::poly_logger::{PolyLogger, PolyHandle};
+use super::stats::LoggerStats;
+
+/// Describes a [PolyLogger](struct.PolyLogger.html) tree which can
+/// be loaded from a TOML, YAML or JSON file.
+///
+/// Each entry in `loggers` becomes a named child of the PolyLogger.
+/// A child has a `kind` of "stdout", "stderr", "file" or "poly", and
+/// the following optional settings:
+/// * level - "off", "error", "warn", "info" (the default), "debug" or "trace".
+///   A "poly" logger takes its level from its children, so has no level.
+/// * filters - a table of target to level overrides, e.g. { hyper = "warn" }
+/// * format - "text" (the default) or "json"
+/// * msg_format - as for [GenLogger::msg_format](struct.GenLogger.html#method.msg_format)
+/// * timestamp_format - as for
+///   [GenLogger::timestamp_format](struct.GenLogger.html#method.timestamp_format)
+/// * filename - the log file, which is mandatory for "file" loggers
+/// * truncate - truncate rather than append to the log file
+/// * color - "auto", "always" or "never" (the default) for stdout and stderr loggers
+/// * escape - "none", "escape", "strip" or "indent", see [Escape](enum.Escape.html).
+///   The default is "escape" for file loggers and "none" otherwise.
+/// * loggers - the children of a nested "poly" logger
+///
+/// # Example
+/// ```
+/// use log::{debug,info};
+/// use poly_logger::{ConfigFormat,LoggerConfig};
+///
+/// let toml = r#"
+///     [loggers.console]
+///     kind = "stderr"
+///     level = "debug"
+///     msg_format = "{level} - {args}"
+///     filters = { "mycrate::db" = "warn" }
+///
+///     [loggers.file]
+///     kind = "file"
+///     filename = "./test.log"
+///     timestamp_format = "%F %T"
+///     truncate = true
+/// "#;
+///
+/// let config = LoggerConfig::parse(toml, ConfigFormat::Toml).unwrap();
+/// let handle = config.init().unwrap();
+/// assert_eq!(handle.names(), vec!["console", "file"]);
+///
+/// info!("This goes to both loggers");
+/// debug!("This only goes to stderr");
+/// ```
+///
+/// Errors name the offending key:
+/// ```
+/// use poly_logger::{ConfigFormat,LoggerConfig};
+///
+/// let json = r#"{ "loggers": { "console": { "kind": "stderr", "level": "loud" } } }"#;
+/// let config = LoggerConfig::parse(json, ConfigFormat::Json).unwrap();
+/// let err = config.build().unwrap_err();
+/// assert_eq!(err.key(), Some("loggers.console.level"));
+///
+/// let yaml = "
+/// loggers:
+///   nested:
+///     kind: poly
+///     level: debug
+/// ";
+/// let config = LoggerConfig::parse(yaml, ConfigFormat::Yaml).unwrap();
+/// let err = config.build().unwrap_err();
+/// assert_eq!(err.key(), Some("loggers.nested.level"));
+/// ```
+///
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoggerConfig {
+    #[serde(default)]
+    pub loggers: BTreeMap<String, ChildConfig>,
+}
+
+/// The configuration of a single child logger.
+/// See [LoggerConfig](struct.LoggerConfig.html) for the meaning of each field.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChildConfig {
+    pub kind: LoggerKind,
+    pub level: Option<String>,
+    #[serde(default)]
+    pub filters: BTreeMap<String, String>,
+    pub format: Option<LogFormat>,
+    pub msg_format: Option<String>,
+    pub timestamp_format: Option<String>,
+    pub filename: Option<String>,
+    pub truncate: Option<bool>,
+    pub color: Option<ColorMode>,
+    pub escape: Option<Escape>,
+    #[serde(default)]
+    pub loggers: BTreeMap<String, ChildConfig>,
+}
+
+/// The type of logger described by a [ChildConfig](struct.ChildConfig.html)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoggerKind {
+    Stdout,
+    Stderr,
+    File,
+    Poly,
+}
+
+/// The syntax of a configuration file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+/// Errors raised while loading a configuration or
+/// building the loggers it describes
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The configuration file could not be read
+    Io { path: PathBuf, error: io::Error },
+    /// The file extension is not one of toml, yaml, yml or json
+    UnknownFormat(PathBuf),
+    /// The configuration could not be parsed
+    Parse { key: String, message: String },
+    /// A configuration value is invalid
+    Invalid { key: String, message: String },
+    /// A logger has already been installed
+    Init(SetLoggerError),
+}
+
+impl ConfigFormat {
+    /// Determines the format from a file extension
+    pub fn from_path(path: &Path) -> Option<ConfigFormat> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "toml" => Some(ConfigFormat::Toml),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            "json" => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+impl LoggerConfig {
+    /// Reads a configuration file, using the file extension
+    /// to determine its format
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<LoggerConfig, ConfigError> {
+        let path = path.as_ref();
+        let format = ConfigFormat::from_path(path)
+            .ok_or_else(|| ConfigError::UnknownFormat(path.to_path_buf()))?;
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| ConfigError::Io{path: path.to_path_buf(), error})?;
+        LoggerConfig::parse(&text, format)
+    }
+
+    /// Parses a configuration from a string
+    pub fn parse(text: &str, format: ConfigFormat) -> Result<LoggerConfig, ConfigError> {
+        match format {
+            ConfigFormat::Toml => {
+                let de = toml::Deserializer::new(text);
+                serde_path_to_error::deserialize(de).map_err(parse_error)
+            },
+            ConfigFormat::Yaml => {
+                let de = serde_yaml::Deserializer::from_str(text);
+                serde_path_to_error::deserialize(de).map_err(parse_error)
+            },
+            ConfigFormat::Json => {
+                let mut de = serde_json::Deserializer::from_str(text);
+                serde_path_to_error::deserialize(&mut de).map_err(parse_error)
+            },
+        }
+    }
+
+    /// Builds the PolyLogger described by this configuration
+    /// without installing it
+    pub fn build(&self) -> Result<PolyLogger, ConfigError> {
+        build_poly(&self.loggers, "loggers")
+    }
+
+    /// Builds the PolyLogger described by this configuration and
+    /// installs it as the application logger. The returned handle
+    /// can be used to modify the children later on.
+    pub fn init(&self) -> Result<PolyHandle, ConfigError> {
+        let logger = self.build()?;
+        let handle = logger.handle();
+        logger.init().map_err(ConfigError::Init)?;
+        Ok(handle)
+    }
+}
+
+// A built child, with its stats unless it is a poly logger
+pub(crate) type Built = (Box<dyn log::Log>, Option<Arc<LoggerStats>>);
+
+impl ChildConfig {
+    /// Builds the logger described by this configuration. The
+    /// key is the path to this child in the configuration, which
+    /// is used in error messages (e.g. "loggers.console").
+    pub fn build(&self, key: &str) -> Result<Box<dyn log::Log>, ConfigError> {
+        self.build_counted(key).map(|(logger, _)| logger)
+    }
+
+    // Builds the logger along with its stats, so that a PolyLogger
+    // can report them
+    pub(crate) fn build_counted(&self, key: &str) -> Result<Built, ConfigError> {
+        let level = self.level.as_deref().unwrap_or("info");
+        let level_filter = parse_level(level, &format!("{}.level", key))?;
+        let mut target_levels = Vec::new();
+        for (target, level) in &self.filters {
+            let level_key = format!("{}.filters.{}", key, target);
+            target_levels.push((target.as_str(), parse_level(level, &level_key)?));
+        }
+
+        // Reject settings which don't apply to this kind of logger
+        let misplaced = match self.kind {
+            LoggerKind::Poly => {
+                if self.level.is_some() { Some("level") }
+                else if self.format.is_some() { Some("format") }
+                else if self.msg_format.is_some() { Some("msg_format") }
+                else if self.timestamp_format.is_some() { Some("timestamp_format") }
+                else if self.filename.is_some() { Some("filename") }
+                else if self.truncate.is_some() { Some("truncate") }
+                else if !self.filters.is_empty() { Some("filters") }
+                else if self.color.is_some() { Some("color") }
+                else if self.escape.is_some() { Some("escape") }
+                else { None }
+            },
+            LoggerKind::File => {
+                if !self.loggers.is_empty() { Some("loggers") }
+                else if self.color.is_some() { Some("color") }
+                else { None }
+            },
+            LoggerKind::Stdout | LoggerKind::Stderr => {
+                if self.filename.is_some() { Some("filename") }
+                else if self.truncate.is_some() { Some("truncate") }
+                else if !self.loggers.is_empty() { Some("loggers") }
+                else { None }
+            },
+        };
+        if let Some(field) = misplaced {
+            return Err(ConfigError::Invalid {
+                key: format!("{}.{}", key, field),
+                message: format!("not valid for a {:?} logger", self.kind).to_lowercase(),
+            });
+        }
+
+        match self.kind {
+            LoggerKind::Stdout => {
+                let mut logger = StdoutLogger::new(level_filter);
+                self.configure(&mut logger, &target_levels, key)?;
+                let stats = logger.stats();
+                Ok((Box::new(logger), Some(stats)))
+            },
+            LoggerKind::Stderr => {
+                let mut logger = StderrLogger::new(level_filter);
+                self.configure(&mut logger, &target_levels, key)?;
+                let stats = logger.stats();
+                Ok((Box::new(logger), Some(stats)))
+            },
+            LoggerKind::File => {
+                let filename_key = format!("{}.filename", key);
+                let filename = self.filename.as_ref().ok_or_else(|| {
+                    ConfigError::Invalid {
+                        key: filename_key.clone(),
+                        message: "required for file loggers".to_string(),
+                    }
+                })?;
+
+                // Check the formats before we create the file
+                self.formatter(key)?;
+
+                let mut file_logger = FileLogger::new(level_filter);
+                file_logger.filename(filename)
+                    .truncate(self.truncate.unwrap_or(false));
+                if let Some(format) = self.format {
+                    file_logger.log_format(format);
+                }
+                if let Some(format) = &self.msg_format {
+                    file_logger.msg_format(format);
+                }
+                if let Some(format) = &self.timestamp_format {
+                    file_logger.timestamp_format(format);
+                }
+                if let Some(escape) = self.escape {
+                    file_logger.escape(escape);
+                }
+                for (target, level_filter) in &target_levels {
+                    file_logger.target_level(target, *level_filter);
+                }
+
+                let logger = file_logger.try_create().map_err(|e| {
+                    ConfigError::Invalid{key: filename_key, message: e.to_string()}
+                })?;
+                let stats = logger.stats();
+                Ok((Box::new(logger), Some(stats)))
+            },
+            LoggerKind::Poly => {
+                let loggers_key = format!("{}.loggers", key);
+                Ok((Box::new(build_poly(&self.loggers, &loggers_key)?), None))
+            },
+        }
+    }
+
+    fn configure<T>(&self, logger: &mut GenLogger<T>,
+                    target_levels: &[(&str, LevelFilter)],
+                    key: &str) -> Result<(), ConfigError>
+    where T: io::Write + Sync + Send + 'static {
+        logger.log_formatter = self.formatter(key)?;
+        for (target, level_filter) in target_levels {
+            logger.target_level(target, *level_filter);
+        }
+        if let Some(mode) = self.color {
+            logger.color(mode);
+        }
+        Ok(())
+    }
+
+    // Builds and validates the formatter, checking the timestamp
+    // format first so that errors name the right key
+    fn formatter(&self, key: &str) -> Result<LogFormatter, ConfigError> {
+        let mut formatter = LogFormatter::new();
+        if let Some(format) = self.format {
+            formatter.format(format);
+        }
+        if let Some(escape) = self.escape {
+            formatter.escape(escape);
+        }
+        if let Some(format) = &self.timestamp_format {
+            formatter.timestamp_format(format);
+            formatter.validate().map_err(|e| ConfigError::Invalid {
+                key: format!("{}.timestamp_format", key),
+                message: e.to_string(),
+            })?;
+        }
+        if let Some(format) = &self.msg_format {
+            formatter.msg_format(format);
+            formatter.validate().map_err(|e| ConfigError::Invalid {
+                key: format!("{}.msg_format", key),
+                message: e.to_string(),
+            })?;
+        }
+        Ok(formatter)
+    }
+}
+
+fn build_poly(loggers: &BTreeMap<String, ChildConfig>, key: &str) -> Result<PolyLogger, ConfigError> {
+    let poly = PolyLogger::new();
+    let handle = poly.handle();
+    for (name, child) in loggers {
+        let (logger, stats) = child.build_counted(&format!("{}.{}", key, name))?;
+        handle.insert(name, Arc::from(logger), stats);
+    }
+    Ok(poly)
+}
+
+fn parse_level(level: &str, key: &str) -> Result<LevelFilter, ConfigError> {
+    LevelFilter::from_str(level).map_err(|_| ConfigError::Invalid {
+        key: key.to_string(),
+        message: format!("invalid level '{}', expected one of \
+                          off, error, warn, info, debug or trace", level),
+    })
+}
+
+fn parse_error<E: fmt::Display>(e: serde_path_to_error::Error<E>) -> ConfigError {
+    // Some deserializers already prefix their messages with the key
+    let key = e.path().to_string();
+    let message = e.inner().to_string();
+    let message = match message.strip_prefix(&format!("{}: ", key)) {
+        Some(m) => m.to_string(),
+        None => message,
+    };
+    ConfigError::Parse{key, message}
+}
+
+impl ConfigError {
+    /// Returns the configuration key this error relates to, if any
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            ConfigError::Parse{key, ..} | ConfigError::Invalid{key, ..} => Some(key),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io{path, error} => {
+                write!(f, "{}: {}", path.display(), error)
+            },
+            ConfigError::UnknownFormat(path) => {
+                write!(f, "{}: unknown config format, expected a \
+                           .toml, .yaml, .yml or .json file", path.display())
+            },
+            ConfigError::Parse{key, message} | ConfigError::Invalid{key, message} => {
+                write!(f, "{}: {}", key, message)
+            },
+            ConfigError::Init(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
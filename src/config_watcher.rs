@@ -0,0 +1,234 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+use log::{error, info};
+use super::config::{LoggerConfig, ChildConfig, ConfigError};
+use super::poly_logger::PolyHandle;
+
+/// Re-reads a logger configuration file when it changes and
+/// applies it to the live [PolyLogger](struct.PolyLogger.html).
+///
+/// The file is polled for changes to its modification time or size.
+/// Only children whose configuration changed are rebuilt, each one
+/// being swapped in place so the other children keep logging
+/// throughout. If the new configuration is invalid it is rejected,
+/// the old one stays active and the reason is logged at Error level.
+///
+/// The watcher thread stops when the ConfigWatcher is dropped.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use log::{debug,info};
+/// use poly_logger::ConfigWatcher;
+///
+/// let path = std::env::temp_dir().join("poly_logger_watch_doc.toml");
+/// std::fs::write(&path, "[loggers.console]\nkind = \"stderr\"\n").unwrap();
+///
+/// let watcher = ConfigWatcher::init(&path, Duration::from_millis(50)).unwrap();
+/// info!("Logged with the initial configuration");
+/// debug!("Not logged yet");
+///
+/// std::fs::write(&path, "[loggers.console]\nkind = \"stderr\"\nlevel = \"debug\"\n\
+///                        msg_format = \"reloaded: {args}\"\n").unwrap();
+/// std::thread::sleep(Duration::from_millis(500));
+/// debug!("Logged with the new configuration");
+/// assert_eq!(watcher.handle().max_level(), log::Level::Debug);
+/// ```
+///
+pub struct ConfigWatcher {
+    handle: PolyHandle,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+// Used to detect a change in the config file
+type FileStamp = (SystemTime, u64);
+
+impl ConfigWatcher {
+    /// Loads the configuration file, installs the PolyLogger it
+    /// describes as the application logger, and starts watching
+    /// the file for changes every interval.
+    pub fn init<P: AsRef<Path>>(path: P, interval: Duration) -> Result<ConfigWatcher, ConfigError> {
+        let path = path.as_ref().to_path_buf();
+        let stamp = file_stamp(&path);
+        let config = LoggerConfig::from_file(&path)?;
+        let handle = config.init()?;
+        Ok(ConfigWatcher::spawn(path, handle, config, stamp, interval))
+    }
+
+    /// Watches a configuration file and applies changes to an
+    /// existing PolyLogger. The current config is the one the
+    /// PolyLogger was built from, and is used to work out which
+    /// children need rebuilding when the file changes.
+    pub fn watch<P: AsRef<Path>>(path: P, handle: PolyHandle,
+                                 current: LoggerConfig, interval: Duration) -> ConfigWatcher {
+        let path = path.as_ref().to_path_buf();
+        let stamp = file_stamp(&path);
+        ConfigWatcher::spawn(path, handle, current, stamp, interval)
+    }
+
+    /// Returns the handle of the PolyLogger being configured
+    pub fn handle(&self) -> &PolyHandle {
+        &self.handle
+    }
+
+    /// Stops watching the configuration file. The current
+    /// configuration remains active.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn spawn(path: PathBuf, handle: PolyHandle, config: LoggerConfig,
+             stamp: Option<FileStamp>, interval: Duration) -> ConfigWatcher {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut watch = Watch{path, handle: handle.clone(), config, stamp};
+        let thread_stop = stop.clone();
+        let thread = thread::Builder::new()
+            .name("poly_logger_config".to_string())
+            .spawn(move || {
+                while !thread_stop.load(Ordering::SeqCst) {
+                    thread::park_timeout(interval);
+                    watch.poll();
+                }
+            })
+            .expect("Failed to spawn config watcher thread");
+
+        ConfigWatcher{handle, stop, thread: Some(thread)}
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// State owned by the watcher thread
+struct Watch {
+    path: PathBuf,
+    handle: PolyHandle,
+    config: LoggerConfig,
+    stamp: Option<FileStamp>,
+}
+
+impl Watch {
+    fn poll(&mut self) {
+        let stamp = file_stamp(&self.path);
+        if stamp == self.stamp {
+            return;
+        }
+        self.stamp = stamp;
+
+        let result = LoggerConfig::from_file(&self.path)
+            .and_then(|config| {
+                config.apply(&self.config, &self.handle)?;
+                Ok(config)
+            });
+
+        match result {
+            Ok(config) => {
+                self.config = config;
+                info!(target: "poly_logger", "Reloaded logging config {}",
+                      self.path.display());
+            },
+            Err(e) => {
+                error!(target: "poly_logger", "Rejected logging config {}: {}",
+                       self.path.display(), e);
+            },
+        }
+    }
+}
+
+fn file_stamp(path: &Path) -> Option<FileStamp> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+impl LoggerConfig {
+    /// Applies this configuration to a PolyLogger that was built
+    /// from the previous configuration. Children that are unchanged
+    /// are left alone, changed children are rebuilt and replaced,
+    /// and children are added or removed to match this configuration.
+    ///
+    /// All new children are built before any changes are made,
+    /// so an invalid configuration leaves the PolyLogger untouched.
+    /// A rebuilt file logger appends to its log file even if truncate
+    /// is set, unless its filename changed.
+    ///
+    /// # Example
+    /// ```
+    /// use log::{Log,Level,Record};
+    /// use poly_logger::{ConfigFormat,LoggerConfig};
+    ///
+    /// let path = std::env::temp_dir().join("poly_logger_apply_doc.log");
+    /// let config = |level| format!("[loggers.file]\nkind = \"file\"\n\
+    ///                               filename = {:?}\ntruncate = true\n\
+    ///                               level = \"{}\"\n", path, level);
+    ///
+    /// let previous = LoggerConfig::parse(&config("info"), ConfigFormat::Toml).unwrap();
+    /// let logger = previous.build().unwrap();
+    /// logger.log(&Record::builder()
+    ///     .args(format_args!("Before the reload"))
+    ///     .level(Level::Info)
+    ///     .build());
+    ///
+    /// let current = LoggerConfig::parse(&config("debug"), ConfigFormat::Toml).unwrap();
+    /// current.apply(&previous, &logger.handle()).unwrap();
+    /// logger.flush();
+    ///
+    /// let contents = std::fs::read_to_string(&path).unwrap();
+    /// assert!(contents.contains("Before the reload"));
+    /// ```
+    pub fn apply(&self, previous: &LoggerConfig, handle: &PolyHandle) -> Result<(), ConfigError> {
+        let mut changed = Vec::new();
+        for (name, child) in &self.loggers {
+            match previous.loggers.get(name) {
+                Some(old) if old == child => {},
+                Some(old) => {
+                    let built = reopened(child, old).build_counted(&format!("loggers.{}", name))?;
+                    changed.push((name, built));
+                },
+                None => {
+                    let built = child.build_counted(&format!("loggers.{}", name))?;
+                    changed.push((name, built));
+                },
+            }
+        }
+
+        for (name, (logger, stats)) in changed {
+            handle.swap(name, Arc::from(logger), stats);
+        }
+        for name in previous.loggers.keys() {
+            if !self.loggers.contains_key(name) {
+                handle.remove(name);
+            }
+        }
+        Ok(())
+    }
+}
+
+// The configuration of a child being rebuilt, with truncate turned
+// off for any log file that the previous configuration had open
+fn reopened(child: &ChildConfig, previous: &ChildConfig) -> ChildConfig {
+    let mut child = child.clone();
+    if child.filename == previous.filename {
+        child.truncate = None;
+    }
+    for (name, nested) in child.loggers.iter_mut() {
+        if let Some(old) = previous.loggers.get(name) {
+            *nested = reopened(nested, old);
+        }
+    }
+    child
+}
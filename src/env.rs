@@ -0,0 +1,91 @@
+use std::str::FromStr;
+use log::LevelFilter;
+use super::log_formatter::LogFormatter;
+
+// Settings read from the environment for a given prefix, e.g.
+// MYAPP_LOG_LEVEL, MYAPP_LOG_FORMAT, MYAPP_LOG_TIMESTAMP_FORMAT,
+// MYAPP_LOG_FILE and MYAPP_LOG_TRUNCATE. Invalid values are
+// reported on stderr and ignored so that code defaults apply.
+pub struct EnvOverrides {
+    pub level_filter: Option<LevelFilter>,
+    pub msg_format: Option<String>,
+    pub timestamp_format: Option<String>,
+    pub file: Option<String>,
+    pub truncate: Option<bool>,
+}
+
+impl EnvOverrides {
+    pub fn read(prefix: &str) -> Self {
+        let mut overrides = EnvOverrides {
+            level_filter: None,
+            msg_format: None,
+            timestamp_format: None,
+            file: None,
+            truncate: None,
+        };
+
+        if let Some((name, value)) = var(prefix, "LEVEL") {
+            match LevelFilter::from_str(value.trim()) {
+                Ok(level_filter) => overrides.level_filter = Some(level_filter),
+                Err(_) => eprintln!("Invalid log level in {}: {}", name, value),
+            }
+        }
+
+        if let Some((name, value)) = var(prefix, "TIMESTAMP_FORMAT") {
+            let mut formatter = LogFormatter::new();
+            formatter.timestamp_format(&value);
+            match formatter.validate() {
+                Ok(_) => overrides.timestamp_format = Some(value),
+                Err(e) => eprintln!("Invalid log format in {}: {}", name, e),
+            }
+        }
+
+        if let Some((name, value)) = var(prefix, "FORMAT") {
+            let mut formatter = LogFormatter::new();
+            formatter.msg_format(&value);
+            match formatter.validate() {
+                Ok(_) => overrides.msg_format = Some(value),
+                Err(e) => eprintln!("Invalid log format in {}: {}", name, e),
+            }
+        }
+
+        if let Some((_, value)) = var(prefix, "FILE") {
+            overrides.file = Some(value);
+        }
+
+        if let Some((name, value)) = var(prefix, "TRUNCATE") {
+            match value.to_lowercase().as_str() {
+                "1" | "true" | "yes" => overrides.truncate = Some(true),
+                "0" | "false" | "no" => overrides.truncate = Some(false),
+                _ => eprintln!("Invalid boolean in {}: {}", name, value),
+            }
+        }
+        overrides
+    }
+
+    // Applies the settings every logger has, leaving the
+    // file settings to the FileLogger
+    pub fn apply(&self, level_filter: &mut LevelFilter, formatter: &mut LogFormatter) {
+        if let Some(level) = self.level_filter {
+            *level_filter = level;
+        }
+        if let Some(format) = &self.timestamp_format {
+            formatter.timestamp_format(format);
+        }
+        if let Some(format) = &self.msg_format {
+            formatter.msg_format(format);
+        }
+    }
+}
+
+// Returns the variable name and its value if set and not empty
+fn var(prefix: &str, suffix: &str) -> Option<(String, String)> {
+    let name = match prefix {
+        "" => format!("LOG_{}", suffix),
+        p => format!("{}_LOG_{}", p, suffix),
+    };
+    match std::env::var(&name) {
+        Ok(value) if !value.is_empty() => Some((name, value)),
+        _ => None,
+    }
+}
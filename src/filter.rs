@@ -0,0 +1,654 @@
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use log::{Level, SetLoggerError};
+use super::stats::{CountedLog, LoggerStats};
+use super::sys;
+
+/// A stage which a [FilteredLogger](struct.FilteredLogger.html)
+/// passes each record through before it reaches the logger
+///
+/// apply() passes the record on by calling next, once or not at
+/// all, and may also pass on records of its own, such as a summary
+/// of what it held back. flush() is called when the logger is
+/// flushed, so that anything still held back can be passed on.
+pub trait Filter: Send + Sync {
+    fn apply(&self, record: &log::Record, next: &mut dyn FnMut(&log::Record));
+
+    fn flush(&self, _next: &mut dyn FnMut(&log::Record)) {}
+}
+
+/// Wraps a logger so that records pass through a chain of
+/// [Filter](trait.Filter.html) stages first
+///
+/// Filters run in the order they were added. Wrapping a PolyLogger
+/// child rather than the PolyLogger applies the filters to that
+/// child only.
+///
+/// If the wrapped logger keeps [LoggerStats](struct.LoggerStats.html),
+/// so does the FilteredLogger: they are the stats of the wrapped
+/// logger, with the records the filters hold back counted as
+/// filtered from when stats() is first called, e.g. by
+/// [PolyLogger::add_counted](struct.PolyLogger.html#method.add_counted).
+///
+/// # Example
+/// ```
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::{CaptureLogger,Dedup,FilteredLogger,RateLimit};
+///
+/// let capture_logger = CaptureLogger::new(LevelFilter::Info);
+/// let capture = capture_logger.handle();
+/// let mut logger = FilteredLogger::new(capture_logger);
+/// logger.filter(Dedup::new())
+///       .filter(RateLimit::per_call_site(1.0, 2));
+///
+/// let log = |msg: &str, line| logger.log(&Record::builder()
+///     .args(format_args!("{}", msg))
+///     .level(Level::Error)
+///     .file(Some("main.rs"))
+///     .line(Some(line))
+///     .build());
+/// for _ in 0..1000 {
+///     log("Connection refused", 10);
+/// }
+/// for i in 0..5 {
+///     log(&format!("Retry {}", i), 20);
+/// }
+///
+/// assert_eq!(capture.messages(), vec![
+///     "Connection refused",
+///     "last message repeated 999 times",
+///     "Retry 0",
+///     "Retry 1",
+/// ]);
+/// ```
+///
+/// # Per Child Example
+/// ```
+/// use log::{error,LevelFilter};
+/// use poly_logger::{Dedup,FileLogger,FilteredLogger,PolyLogger,StderrLogger};
+///
+/// // Only the console is deduplicated
+/// let mut console = FilteredLogger::new(StderrLogger::new(LevelFilter::Info));
+/// console.filter(Dedup::new());
+///
+/// let mut file = FileLogger::new(LevelFilter::Info);
+/// file.filename("./test.log");
+///
+/// let mut pl = PolyLogger::new();
+/// pl.add_named("console", console);
+/// pl.add_named("file", file.create());
+/// pl.init().unwrap();
+///
+/// for _ in 0..1000 {
+///     error!("Connection refused");
+/// }
+/// log::logger().flush();
+/// ```
+///
+/// # Stats Example
+/// ```
+/// use log::{Log,Level,LevelFilter,Record};
+/// use poly_logger::{CaptureLogger,FilteredLogger,PolyLogger,RateLimit};
+///
+/// let mut logger = FilteredLogger::new(CaptureLogger::new(LevelFilter::Info));
+/// logger.filter(RateLimit::per_call_site(0.0, 2));
+///
+/// let mut pl = PolyLogger::new();
+/// pl.add_counted("limited", logger);
+/// for _ in 0..5 {
+///     pl.log(&Record::builder()
+///         .args(format_args!("Connection refused"))
+///         .level(Level::Error)
+///         .build());
+/// }
+///
+/// let stats = pl.handle().stats("limited").unwrap();
+/// assert_eq!(stats.written(Level::Error), 2);
+/// assert_eq!(stats.filtered, 3);
+/// ```
+///
+pub struct FilteredLogger<L: log::Log> {
+    logger: L,
+    filters: Vec<Box<dyn Filter>>,
+    // The wrapped logger's stats, once asked for
+    stats: OnceLock<Arc<LoggerStats>>,
+}
+
+impl<L: log::Log + 'static> FilteredLogger<L> {
+    /// Instantiates a FilteredLogger with no filters
+    pub fn new(logger: L) -> FilteredLogger<L> {
+        FilteredLogger {
+            logger,
+            filters: Vec::new(),
+            stats: OnceLock::new(),
+        }
+    }
+
+    /// Initializes the log interface using this FilteredLogger
+    /// as a boxed logger. This moves self so is the last
+    /// method to call on this object.
+    ///
+    /// The maximum level is left as it is, so call
+    /// log::set_max_level() if it needs raising.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        log::set_boxed_logger(Box::new(self))
+    }
+
+    /// Adds a filter to the end of the chain
+    pub fn filter<F: Filter + 'static>(&mut self, filter: F) -> &mut Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    // Passes a record through the filters from the given one on,
+    // counting it if a filter holds it back. Records a filter passes
+    // on in its place, such as summaries, do not count as the record.
+    fn apply(&self, filters: &[Box<dyn Filter>], record: &log::Record) {
+        match filters.split_first() {
+            Some((filter, rest)) => {
+                let mut passed = false;
+                filter.apply(record, &mut |r| {
+                    passed |= std::ptr::eq(r, record);
+                    self.apply(rest, r)
+                });
+                if !passed {
+                    self.filtered();
+                }
+            },
+            None => self.logger.log(record),
+        }
+    }
+
+    fn filtered(&self) {
+        if let Some(stats) = self.stats.get() {
+            stats.filtered();
+        }
+    }
+}
+
+impl<L: log::Log + 'static> log::Log for FilteredLogger<L> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.logger.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            let _in_logger = sys::enter_logger();
+            self.apply(&self.filters, record);
+        } else {
+            self.filtered();
+        }
+    }
+
+    fn flush(&self) {
+        for (i, filter) in self.filters.iter().enumerate() {
+            let rest = &self.filters[i + 1..];
+            filter.flush(&mut |r| self.apply(rest, r));
+        }
+        self.logger.flush();
+    }
+}
+
+/// What a [RateLimit](struct.RateLimit.html) keeps a separate
+/// token bucket for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimitKey {
+    /// The file and line of the log call
+    CallSite,
+    /// The message template: the call site, plus the text of a
+    /// message without arguments. Records from one log call share a
+    /// bucket whatever their arguments, and records built without a
+    /// file and line share one only if their text matches.
+    Message,
+}
+
+/// A [Filter](trait.Filter.html) which limits how often records
+/// are passed on, using a token bucket per call site or message
+/// template
+///
+/// Each bucket holds up to burst tokens and refills at rate tokens
+/// per second. A record which finds its bucket empty is dropped. The
+/// next record passed on for that bucket is preceded by a Warn record
+/// saying how many were dropped.
+pub struct RateLimit {
+    key: RateLimitKey,
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    dropped: u64,
+}
+
+// Buckets are forgotten beyond this many, to bound the memory
+// used when keyed by many call sites or message texts
+const MAX_BUCKETS: usize = 10_000;
+
+impl RateLimit {
+    /// Instantiates a RateLimit with a bucket per call site
+    pub fn per_call_site(rate: f64, burst: u32) -> RateLimit {
+        RateLimit::new(RateLimitKey::CallSite, rate, burst)
+    }
+
+    /// Instantiates a RateLimit with a bucket per message template
+    ///
+    /// # Example
+    /// ```
+    /// use log::{Log,LevelFilter,Record,Level};
+    /// use poly_logger::{CaptureLogger,FilteredLogger,RateLimit};
+    ///
+    /// let capture_logger = CaptureLogger::new(LevelFilter::Info);
+    /// let capture = capture_logger.handle();
+    /// let mut logger = FilteredLogger::new(capture_logger);
+    /// logger.filter(RateLimit::per_message(0.0, 1));
+    ///
+    /// // One log call with varying arguments shares a bucket
+    /// for i in 0..3 {
+    ///     logger.log(&Record::builder()
+    ///         .args(format_args!("Retry {}", i))
+    ///         .level(Level::Warn)
+    ///         .file(Some("main.rs"))
+    ///         .line(Some(10))
+    ///         .build());
+    /// }
+    /// // Records without a call site are told apart by their text
+    /// for msg in [format_args!("one"), format_args!("two")] {
+    ///     logger.log(&Record::builder().args(msg).level(Level::Warn).build());
+    /// }
+    ///
+    /// assert_eq!(capture.messages(), vec!["Retry 0", "one", "two"]);
+    /// ```
+    pub fn per_message(rate: f64, burst: u32) -> RateLimit {
+        RateLimit::new(RateLimitKey::Message, rate, burst)
+    }
+
+    /// Instantiates a RateLimit allowing rate records per second
+    /// and bursts of up to burst records for each key
+    pub fn new(key: RateLimitKey, rate: f64, burst: u32) -> RateLimit {
+        RateLimit {
+            key,
+            rate: rate.max(0.0),
+            burst: burst.max(1) as f64,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Never formats the arguments, so keying costs the same
+    // whatever the message
+    fn key(&self, record: &log::Record) -> String {
+        let call_site = format!("{}:{}",
+            record.file().unwrap_or(record.target()),
+            record.line().unwrap_or_default());
+        match (self.key, record.args().as_str()) {
+            (RateLimitKey::Message, Some(text)) => format!("{} {}", call_site, text),
+            _ => call_site,
+        }
+    }
+}
+
+impl Filter for RateLimit {
+    fn apply(&self, record: &log::Record, next: &mut dyn FnMut(&log::Record)) {
+        let key = self.key(record);
+        let now = Instant::now();
+
+        let dropped = {
+            let mut buckets = self.buckets.lock().unwrap();
+            if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&key) {
+                buckets.clear();
+            }
+            let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+                tokens: self.burst,
+                updated: now,
+                dropped: 0,
+            });
+
+            let refill = now.duration_since(bucket.updated).as_secs_f64() * self.rate;
+            bucket.tokens = (bucket.tokens + refill).min(self.burst);
+            bucket.updated = now;
+            if bucket.tokens < 1.0 {
+                bucket.dropped += 1;
+                return;
+            }
+            bucket.tokens -= 1.0;
+            std::mem::take(&mut bucket.dropped)
+        };
+
+        if dropped > 0 {
+            next(&log::Record::builder()
+                .args(format_args!("Rate limit dropped {} records from {}", dropped, key))
+                .level(Level::Warn)
+                .target(record.target())
+                .file(record.file())
+                .line(record.line())
+                .build());
+        }
+        next(record);
+    }
+}
+
+/// A [Filter](trait.Filter.html) which collapses runs of identical
+/// records into one, followed by "last message repeated N times"
+///
+/// Records are identical if they have the same level, target and
+/// message. The repeat count is passed on when a different record
+/// arrives or the logger is flushed, and, while the repeats go on,
+/// by the first repeat after each interval(). The default interval
+/// is 30s.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::{CaptureLogger,Dedup,FilteredLogger};
+///
+/// let capture_logger = CaptureLogger::new(LevelFilter::Info);
+/// let capture = capture_logger.handle();
+/// let mut dedup = Dedup::new();
+/// dedup.interval(Duration::from_millis(50));
+/// let mut logger = FilteredLogger::new(capture_logger);
+/// logger.filter(dedup);
+///
+/// let log = || logger.log(&Record::builder()
+///     .args(format_args!("Connection refused"))
+///     .level(Level::Error)
+///     .build());
+/// log();
+/// log();
+/// std::thread::sleep(Duration::from_millis(60));
+/// log();
+///
+/// assert_eq!(capture.messages(), vec![
+///     "Connection refused",
+///     "last message repeated 2 times",
+/// ]);
+/// ```
+pub struct Dedup {
+    interval: Duration,
+    last: Mutex<Option<Repeated>>,
+}
+
+struct Repeated {
+    level: Level,
+    target: String,
+    message: String,
+    file: Option<String>,
+    line: Option<u32>,
+    count: u64,
+    reported: Instant,
+}
+
+// A repeat count taken from a Repeated, to be passed
+// on once the lock is released
+struct Summary {
+    level: Level,
+    target: String,
+    file: Option<String>,
+    line: Option<u32>,
+    count: u64,
+}
+
+impl Dedup {
+    /// Instantiates a Dedup filter
+    pub fn new() -> Dedup {
+        Dedup {
+            interval: Duration::from_secs(30),
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Sets how often the repeat count is passed on while
+    /// a record keeps repeating
+    pub fn interval(&mut self, interval: Duration) -> &mut Self {
+        self.interval = interval;
+        self
+    }
+}
+
+impl Default for Dedup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repeated {
+    fn new(record: &log::Record) -> Repeated {
+        Repeated {
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+            file: record.file().map(|f| f.to_string()),
+            line: record.line(),
+            count: 0,
+            reported: Instant::now(),
+        }
+    }
+
+    fn matches(&self, record: &log::Record) -> bool {
+        self.level == record.level() &&
+            self.target == record.target() &&
+            args_eq(record.args(), &self.message)
+    }
+
+    // Takes the repeats counted so far, if any
+    fn summary(&mut self) -> Option<Summary> {
+        if self.count == 0 {
+            return None;
+        }
+        let count = std::mem::take(&mut self.count);
+        self.reported = Instant::now();
+        Some(Summary {
+            level: self.level,
+            target: self.target.clone(),
+            file: self.file.clone(),
+            line: self.line,
+            count,
+        })
+    }
+}
+
+// Compares formatted arguments with a text without allocating,
+// stopping at the first difference
+fn args_eq(args: &fmt::Arguments, text: &str) -> bool {
+    if let Some(s) = args.as_str() {
+        return s == text;
+    }
+    struct Compare<'a>(&'a str);
+    impl Write for Compare<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            match self.0.strip_prefix(s) {
+                Some(rest) => {
+                    self.0 = rest;
+                    Ok(())
+                },
+                None => Err(fmt::Error),
+            }
+        }
+    }
+    let mut rest = Compare(text);
+    rest.write_fmt(*args).is_ok() && rest.0.is_empty()
+}
+
+impl Summary {
+    fn report(&self, next: &mut dyn FnMut(&log::Record)) {
+        next(&log::Record::builder()
+            .args(format_args!("last message repeated {} times", self.count))
+            .level(self.level)
+            .target(&self.target)
+            .file(self.file.as_deref())
+            .line(self.line)
+            .build());
+    }
+}
+
+impl Filter for Dedup {
+    fn apply(&self, record: &log::Record, next: &mut dyn FnMut(&log::Record)) {
+        // Records are passed on after the lock is released, so that
+        // a later stage which logs again cannot deadlock on it
+        let (summary, repeat) = {
+            let mut last = self.last.lock().unwrap();
+            match last.as_mut() {
+                Some(repeated) if repeated.matches(record) => {
+                    repeated.count += 1;
+                    match repeated.reported.elapsed() >= self.interval {
+                        true => (repeated.summary(), true),
+                        false => (None, true),
+                    }
+                },
+                previous => {
+                    let summary = previous.and_then(|r| r.summary());
+                    *last = Some(Repeated::new(record));
+                    (summary, false)
+                },
+            }
+        };
+
+        if let Some(summary) = summary {
+            summary.report(next);
+        }
+        if !repeat {
+            next(record);
+        }
+    }
+
+    fn flush(&self, next: &mut dyn FnMut(&log::Record)) {
+        let summary = self.last.lock().unwrap().as_mut().and_then(|r| r.summary());
+        if let Some(summary) = summary {
+            summary.report(next);
+        }
+    }
+}
+
+impl<L: CountedLog + 'static> CountedLog for FilteredLogger<L> {
+    fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.get_or_init(|| self.logger.stats()).clone()
+    }
+}
+
+/// A [Filter](trait.Filter.html) which passes on a random sample
+/// of the records at each level
+///
+/// Each level has a probability of a record being kept, which is 1.0
+/// (keep everything) unless set with rate(). With first(n) the first
+/// n records from each call site are always kept before sampling
+/// starts, so that rare messages are not lost.
+///
+/// # Example
+/// Keeps 1% of Debug and Trace records, after the first 10 from each
+/// call site, and all Info, Warn and Error records
+/// ```
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::{CaptureLogger,CountedLog,FilteredLogger,Sampler};
+///
+/// let capture_logger = CaptureLogger::new(LevelFilter::Trace);
+/// let capture = capture_logger.handle();
+///
+/// let mut sampler = Sampler::new();
+/// sampler.rate(Level::Debug, 0.01)
+///        .rate(Level::Trace, 0.01)
+///        .first(10);
+///
+/// let mut logger = FilteredLogger::new(capture_logger);
+/// logger.filter(sampler);
+/// let stats = logger.stats();
+///
+/// for level in &[Level::Debug, Level::Warn] {
+///     for _ in 0..10_000 {
+///         logger.log(&Record::builder()
+///             .args(format_args!("Polling"))
+///             .level(*level)
+///             .file(Some("poll.rs"))
+///             .line(Some(1))
+///             .build());
+///     }
+/// }
+///
+/// let records = capture.records();
+/// let debug = records.iter().filter(|r| r.level == Level::Debug).count();
+/// let warn = records.iter().filter(|r| r.level == Level::Warn).count();
+/// assert_eq!(warn, 10_000);
+/// assert!(debug > 10 + 50 && debug < 10 + 200);
+/// assert_eq!(stats.snapshot().filtered, 10_000 - debug as u64);
+/// ```
+pub struct Sampler {
+    // The probability of keeping a record, indexed by level
+    rates: [f64; 6],
+    first: u64,
+    seen: Mutex<HashMap<String, u64>>,
+    random: AtomicU64,
+}
+
+impl Sampler {
+    /// Instantiates a Sampler which keeps every record
+    pub fn new() -> Sampler {
+        Sampler {
+            rates: [1.0; 6],
+            first: 0,
+            seen: Mutex::new(HashMap::new()),
+            random: AtomicU64::new(RandomState::new().hash_one(Instant::now())),
+        }
+    }
+
+    /// Sets the probability, from 0.0 to 1.0, of keeping
+    /// a record at the given level
+    pub fn rate(&mut self, level: Level, probability: f64) -> &mut Self {
+        self.rates[level as usize] = probability.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Keeps the first n records from each call site (file and
+    /// line) before sampling them. The default is 0.
+    pub fn first(&mut self, n: u64) -> &mut Self {
+        self.first = n;
+        self
+    }
+
+    // True for the first n records from the record's call site
+    fn is_first(&self, record: &log::Record) -> bool {
+        if self.first == 0 {
+            return false;
+        }
+        let site = format!("{}:{}",
+            record.file().unwrap_or(record.target()),
+            record.line().unwrap_or_default());
+        let mut seen = self.seen.lock().unwrap();
+        if seen.len() >= MAX_BUCKETS && !seen.contains_key(&site) {
+            seen.clear();
+        }
+        let count = seen.entry(site).or_insert(0);
+        *count += 1;
+        *count <= self.first
+    }
+
+    // A uniform random number in [0, 1) from splitmix64
+    fn random(&self) -> f64 {
+        let mut z = self.random.fetch_add(0x9e3779b97f4a7c15, Ordering::Relaxed)
+            .wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Filter for Sampler {
+    fn apply(&self, record: &log::Record, next: &mut dyn FnMut(&log::Record)) {
+        let rate = self.rates[record.level() as usize];
+        if rate >= 1.0 || self.is_first(record) || self.random() < rate {
+            next(record);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::cell::Cell;
+use std::sync::{Mutex, Once};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use super::sys::{self, FlagGuard};
+
+// Drains the queue of an async sink, giving up at the deadline.
+// Returns false once the sink is gone, so it can be forgotten.
+type Shutdown = Box<dyn Fn(Instant) -> bool + Send>;
+
+static SHUTDOWN: Mutex<Vec<Shutdown>> = Mutex::new(Vec::new());
+
+// The timeout used at exit, in millis
+static EXIT_TIMEOUT: AtomicU64 = AtomicU64::new(5000);
+
+// The number of guards not yet dropped
+static LIVE_GUARDS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static IN_SHUTDOWN: Cell<bool> = const { Cell::new(false) };
+    // The deadline of the shutdown in progress on this thread
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Flushes every logger when dropped
+///
+/// Returned by the init_with_guard() methods of
+/// [GenLogger](struct.GenLogger.html#method.init_with_guard),
+/// [PolyLogger](struct.PolyLogger.html#method.init_with_guard) and
+/// [FileLogger](struct.FileLogger.html#method.init_with_guard). Keep it
+/// in a variable in main(), e.g. `let _guard = ...`, so that when main
+/// returns any queued records of asynchronous loggers such as the
+/// [HttpLogger](struct.HttpLogger.html) are sent, waiting up to the
+/// timeout(), and every logger is flushed.
+///
+/// As destructors do not run on std::process::exit(), the same is also
+/// done at exit on Unix while a guard is alive, waiting up to the
+/// timeout set by [set_exit_timeout](fn.set_exit_timeout.html).
+///
+/// Asynchronous and buffering loggers are given what is left of the
+/// timeout: the [HttpLogger](struct.HttpLogger.html) sends its queue,
+/// the [TcpLogger](struct.TcpLogger.html) sends its buffer or moves it
+/// to its spool file, and the
+/// [RingBufferLogger](struct.RingBufferLogger.html) flushes its dump
+/// writer.
+///
+/// # Example
+/// ```
+/// use log::{info,LevelFilter};
+/// use poly_logger::{PolyLogger,StderrLogger};
+///
+/// let mut pl = PolyLogger::new();
+/// pl.add(StderrLogger::new(LevelFilter::Info));
+/// let _guard = pl.init_with_guard().unwrap();
+///
+/// info!("Flushed when main returns, or on std::process::exit()");
+/// ```
+///
+#[must_use = "the loggers are flushed as soon as the guard is dropped"]
+pub struct FlushGuard {
+    timeout: Duration,
+}
+
+impl FlushGuard {
+    pub(crate) fn new() -> FlushGuard {
+        static AT_EXIT: Once = Once::new();
+        AT_EXIT.call_once(|| {
+            #[cfg(unix)]
+            unsafe {
+                libc::atexit(flush_at_exit);
+            }
+        });
+        LIVE_GUARDS.fetch_add(1, Ordering::SeqCst);
+        FlushGuard {
+            timeout: Duration::from_millis(EXIT_TIMEOUT.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Sets how long this guard waits for asynchronous loggers to
+    /// send their queued records when dropped. The default is the
+    /// exit timeout, which is 5s unless changed by
+    /// [set_exit_timeout](fn.set_exit_timeout.html).
+    ///
+    /// # Example
+    /// Gives up on a server which never answers
+    /// ```
+    /// use std::net::TcpListener;
+    /// use std::time::{Duration,Instant};
+    /// use log::{info,LevelFilter};
+    /// use poly_logger::{HttpLogger,PolyLogger};
+    ///
+    /// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    /// let url = format!("http://{}/", listener.local_addr().unwrap());
+    /// let _server = std::thread::spawn(move || {
+    ///     let (_stream, _) = listener.accept().unwrap();
+    ///     std::thread::sleep(Duration::from_secs(60));
+    /// });
+    ///
+    /// let mut pl = PolyLogger::new();
+    /// pl.add(HttpLogger::new(LevelFilter::Info, &url).unwrap());
+    /// let mut guard = pl.init_with_guard().unwrap();
+    /// guard.timeout(Duration::from_millis(200));
+    ///
+    /// info!("never answered");
+    /// let start = Instant::now();
+    /// drop(guard);
+    /// assert!(start.elapsed() < Duration::from_secs(2));
+    /// ```
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// Sets how long to wait for asynchronous loggers to send their
+/// queued records on std::process::exit(), and the default timeout of
+/// guards created afterwards. The default is 5s.
+pub fn set_exit_timeout(timeout: Duration) {
+    EXIT_TIMEOUT.store(timeout.as_millis() as u64, Ordering::Relaxed);
+}
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        shutdown(self.timeout);
+        LIVE_GUARDS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn flush_at_exit() {
+    // Once every guard is dropped, the loggers were already flushed
+    // and may be in the middle of being torn down
+    if LIVE_GUARDS.load(Ordering::SeqCst) == 0 {
+        return;
+    }
+    // Unwinding into C is undefined, so contain any panic
+    let _ = std::panic::catch_unwind(|| {
+        shutdown(Duration::from_millis(EXIT_TIMEOUT.load(Ordering::Relaxed)));
+    });
+}
+
+// Registers an async sink to be drained on shutdown
+pub(crate) fn on_shutdown<F>(drain: F)
+where F: Fn(Instant) -> bool + Send + 'static {
+    if let Ok(mut sinks) = SHUTDOWN.lock() {
+        sinks.push(Box::new(drain));
+    }
+}
+
+// Returns when a flush with the given timeout must give up, which
+// is sooner if a shutdown with a nearer deadline is in progress
+pub(crate) fn flush_deadline(timeout: Duration) -> Instant {
+    let deadline = Instant::now() + timeout;
+    match DEADLINE.try_with(|d| d.get()) {
+        Ok(Some(shutdown)) => deadline.min(shutdown),
+        _ => deadline,
+    }
+}
+
+// Drains the async sinks, then flushes the installed logger, all
+// within the timeout. Does nothing if called from within a logger
+// or a shutdown, e.g. by a logger which drops a guard.
+fn shutdown(timeout: Duration) {
+    if sys::in_logger() {
+        return;
+    }
+    let _guard = match FlagGuard::set(&IN_SHUTDOWN) {
+        Some(guard) => guard,
+        None => return,
+    };
+
+    let deadline = Instant::now() + timeout;
+    let _ = DEADLINE.try_with(|d| d.set(Some(deadline)));
+    if let Ok(mut sinks) = SHUTDOWN.lock() {
+        sinks.retain(|drain| drain(deadline));
+    }
+    log::logger().flush();
+    let _ = DEADLINE.try_with(|d| d.set(None));
+}
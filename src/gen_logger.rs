@@ -1,7 +1,13 @@
 use std::io::{Write};
-use std::sync::{Mutex};
+use std::sync::{Arc, Mutex};
 use log::{LevelFilter, SetLoggerError};
-use super::log_formatter::LogFormatter;
+use super::log_formatter::{LogFormatter, LogFormat, Escape};
+use super::log_event::{LogEvent, RecordEvent, EventFields};
+use super::flush_guard::FlushGuard;
+use super::stats::{CountedLog, LoggerStats};
+use super::env::EnvOverrides;
+use super::color::{ColorMode, ColorPalette};
+use super::sys;
 
 /// Implements a generic logger for use with different types of writers
 /// 
@@ -33,8 +39,12 @@ use super::log_formatter::LogFormatter;
 ///
 pub struct GenLogger<T: Write + Sync + Send + 'static> {
     level_filter: LevelFilter,
+    target_levels: Vec<(String, LevelFilter)>,
+    is_terminal: bool,
+    can_color: bool,
     pub log_formatter: LogFormatter,
     writer: Mutex<T>,
+    stats: Arc<LoggerStats>,
 }
 
 impl<T> GenLogger<T>
@@ -43,9 +53,13 @@ where T: Write + Sync + Send + 'static {
     /// filter and a Writer instance
     pub fn new(level_filter: LevelFilter, writer: T) -> Self {
         GenLogger {
-            level_filter: level_filter,
+            level_filter,
+            target_levels: Vec::new(),
+            is_terminal: false,
+            can_color: true,
             log_formatter: LogFormatter::new(),
             writer: Mutex::new(writer),
+            stats: Arc::new(LoggerStats::new()),
         }
     }
 
@@ -53,10 +67,223 @@ where T: Write + Sync + Send + 'static {
     /// as a boxed logger. This moves self so is the last 
     /// method to call on this object.
     pub fn init(self) -> Result<(), SetLoggerError> {
-        log::set_max_level(self.level_filter);
+        log::set_max_level(self.max_level_filter());
         log::set_boxed_logger(Box::new(self))
     }
 
+    /// Initializes the log interface like init(), returning a
+    /// [FlushGuard](struct.FlushGuard.html) which flushes this
+    /// logger when dropped
+    pub fn init_with_guard(self) -> Result<FlushGuard, SetLoggerError> {
+        self.init()?;
+        Ok(FlushGuard::new())
+    }
+
+    /// Sets the level filter for this logger
+    pub fn level_filter(&mut self, level_filter: LevelFilter) -> &mut Self {
+        self.level_filter = level_filter;
+        self
+    }
+
+    /// Sets when to colorize output with ANSI escape codes. The
+    /// default is ColorMode::Never.
+    ///
+    /// With ColorMode::Auto, color is only used if the writer is a
+    /// terminal and the NO_COLOR environment variable is not set.
+    /// Only [StdoutLogger](struct.StdoutLogger.html) and
+    /// [StderrLogger](struct.StderrLogger.html) can detect a terminal,
+    /// so Auto never colors any other writer. A
+    /// [FileLogger](struct.FileLogger.html) is never colored, even
+    /// with ColorMode::Always.
+    ///
+    /// When color is on, the {level} placeholder is colored according
+    /// to the palette, and the {color} and {/color} placeholders start
+    /// and end the level color at any point in the msg_format. These
+    /// placeholders are empty when color is off.
+    ///
+    /// # Example
+    /// ```
+    /// use log::{info,LevelFilter};
+    /// use poly_logger::{ColorMode,StderrLogger};
+    ///
+    /// let mut logger = StderrLogger::new(LevelFilter::Info);
+    /// logger.color(ColorMode::Auto)
+    ///       .msg_format("{color}[{timestamp}]{/color} {level} - {args}");
+    /// logger.init().unwrap();
+    /// info!("This is a colorful INFO message on a terminal");
+    /// ```
+    pub fn color(&mut self, mode: ColorMode) -> &mut Self {
+        self.log_formatter.color(self.can_color && mode.enabled(self.is_terminal));
+        self
+    }
+
+    /// Sets the colors used for each level
+    pub fn color_palette(&mut self, palette: ColorPalette) -> &mut Self {
+        self.log_formatter.color_palette(palette);
+        self
+    }
+
+    /// Colors the whole line rather than just the {level} placeholder
+    pub fn color_line(&mut self, color_line: bool) -> &mut Self {
+        self.log_formatter.color_line(color_line);
+        self
+    }
+
+    /// Indents each message by the given number of spaces per
+    /// [scope](fn.scope.html) the logging thread is in. The default
+    /// of 0 turns indentation off.
+    ///
+    /// # Example
+    /// ```
+    /// use log::{info,LevelFilter};
+    /// use poly_logger::{scope,StderrLogger};
+    ///
+    /// let mut logger = StderrLogger::new(LevelFilter::Info);
+    /// logger.indent(2)
+    ///       .msg_format("{level} {args} ({scope})");
+    /// logger.init().unwrap();
+    ///
+    /// let _etl = scope("etl");
+    /// info!("Starting");
+    /// let _load = scope("load_customers");
+    /// info!("Indented by four spaces");
+    /// ```
+    pub fn indent(&mut self, width: usize) -> &mut Self {
+        self.log_formatter.indent(width);
+        self
+    }
+
+    /// Sets how control characters in the message and other fields
+    /// which may hold user input are written in the Text format. The
+    /// default is Escape::None, except for a
+    /// [FileLogger](struct.FileLogger.html) which uses Escape::Escape.
+    ///
+    /// # Example
+    /// ```
+    /// use log::{info,LevelFilter};
+    /// use poly_logger::{Escape,StderrLogger};
+    ///
+    /// let mut logger = StderrLogger::new(LevelFilter::Info);
+    /// logger.escape(Escape::Escape);
+    /// logger.init().unwrap();
+    ///
+    /// let user = "dave\n[2020-01-01T00:00:00Z] INFO Forged line";
+    /// info!("Login failed for {}", user); // Written on one line
+    /// ```
+    pub fn escape(&mut self, escape: Escape) -> &mut Self {
+        self.log_formatter.escape(escape);
+        self
+    }
+
+    // Set by loggers which know their writer is a terminal
+    pub(crate) fn is_terminal(&mut self, is_terminal: bool) -> &mut Self {
+        self.is_terminal = is_terminal;
+        self
+    }
+
+    // Turns color off for good, for writers such as
+    // files which must not contain escape codes
+    pub(crate) fn no_color(&mut self) -> &mut Self {
+        self.can_color = false;
+        self.log_formatter.color(false);
+        self
+    }
+
+    /// Overrides settings from environment variables with the given
+    /// prefix. Call this after setting the code defaults so that the
+    /// environment takes precedence. For a prefix of "MYAPP":
+    /// * MYAPP_LOG_LEVEL - e.g. "debug"
+    /// * MYAPP_LOG_FORMAT - see msg_format()
+    /// * MYAPP_LOG_TIMESTAMP_FORMAT - see timestamp_format()
+    ///
+    /// Invalid values are reported on stderr and ignored.
+    ///
+    /// # Example
+    /// ```
+    /// use log::{debug,LevelFilter};
+    /// use poly_logger::StderrLogger;
+    ///
+    /// std::env::set_var("MYAPP_LOG_LEVEL", "debug");
+    /// std::env::set_var("MYAPP_LOG_FORMAT", "{level} {args}");
+    ///
+    /// let mut logger = StderrLogger::new(LevelFilter::Info);
+    /// logger.msg_format("[{timestamp}] {level} {args}")
+    ///       .env_overrides("MYAPP");
+    /// logger.init().unwrap();
+    /// debug!("Debug enabled by the environment");
+    /// ```
+    pub fn env_overrides(&mut self, prefix: &str) -> &mut Self {
+        EnvOverrides::read(prefix).apply(&mut self.level_filter, &mut self.log_formatter);
+        self
+    }
+
+    /// Overrides the level filter for records whose target is
+    /// the given target or one of its submodules. For example
+    /// target_level("hyper", LevelFilter::Warn) quietens the
+    /// hyper crate, and the most specific matching target wins.
+    pub fn target_level(&mut self, target: &str, level_filter: LevelFilter) -> &mut Self {
+        self.target_levels.retain(|(t, _)| t != target);
+        self.target_levels.push((target.to_string(), level_filter));
+        self
+    }
+
+    /// Returns the most verbose level enabled for any target,
+    /// which is the level a PolyLogger sends this logger
+    ///
+    /// # Example
+    /// ```
+    /// use log::{Log,Level,LevelFilter,Metadata};
+    /// use poly_logger::StderrLogger;
+    ///
+    /// let mut logger = StderrLogger::new(LevelFilter::Info);
+    /// logger.target_level("myapp::db", LevelFilter::Debug);
+    /// assert_eq!(logger.max_level_filter(), LevelFilter::Debug);
+    ///
+    /// // A record without a target gets the default level
+    /// let debug = |target| Metadata::builder().level(Level::Debug).target(target).build();
+    /// assert!(!logger.enabled(&debug("")));
+    /// assert!(logger.enabled(&debug("myapp::db")));
+    /// ```
+    pub fn max_level_filter(&self) -> LevelFilter {
+        self.target_levels.iter()
+            .map(|(_, l)| *l)
+            .fold(self.level_filter, std::cmp::max)
+    }
+
+    fn level_filter_for(&self, target: &str) -> LevelFilter {
+        self.target_levels.iter()
+            .filter(|(t, _)| {
+                target.starts_with(t.as_str()) &&
+                    (target.len() == t.len() || target[t.len()..].starts_with("::"))
+            })
+            .max_by_key(|(t, _)| t.len())
+            .map(|(_, l)| *l)
+            .unwrap_or(self.level_filter)
+    }
+
+    /// Sets the output format. The default is LogFormat::Text, which
+    /// uses the msg_format. LogFormat::Json writes one JSON object
+    /// per line instead.
+    ///
+    /// # Example
+    /// ```
+    /// use log::{info,LevelFilter};
+    /// use poly_logger::{LogFormat,StdoutLogger};
+    ///
+    /// let mut logger = StdoutLogger::new(LevelFilter::Info);
+    /// logger.log_format(LogFormat::Json);
+    /// logger.init().unwrap();
+    /// info!(user = "dave"; "This is a JSON message");
+    /// // Output is something like:
+    /// // {"timestamp":"2020-09-27T20:52:57.909459-04:00","level":"INFO",
+    /// //  "target":"doc","file":"src/main.rs","line":8,
+    /// //  "message":"This is a JSON message","kv":{"user":"dave"}}
+    /// ```
+    pub fn log_format(&mut self, format: LogFormat) -> &mut Self {
+        self.log_formatter.format(format);
+        self
+    }
+
     /// Sets the timestamp format to use in our log messages.
     ///
     /// The format string may be any valid format from the
@@ -70,7 +297,7 @@ where T: Write + Sync + Send + 'static {
     /// The default timestamp format of '%+' (ISO 8601 / RFC 3339 date & time format)
     /// will be used if you do not call timestamp_format(<format>) on your logger.
     ///
-    pub fn timestamp_format(&mut self, format: &'static str) -> &mut Self {
+    pub fn timestamp_format(&mut self, format: &str) -> &mut Self {
         self.log_formatter.timestamp_format(format);
         self
     }
@@ -80,10 +307,19 @@ where T: Write + Sync + Send + 'static {
     /// The format can use any combination of the following placeholders
     /// * {timestamp} - Date/time stamp of this message
     /// * {level} - The [log::Level](https://docs.rs/log/0.4.1/log/enum.Level.html)
-    ///           for this message
+    ///   for this message
     /// * {file} - The Rust source file where the log message was generated
     /// * {line} - The line in the Rust source file where the log message was generated
     /// * {args} - The log message itself
+    /// * {target} - The target of the log message
+    /// * {module} - The Rust module path where the log message was generated
+    /// * {thread} - The name of the thread which logged the message, or its id
+    /// * {mdc} - Every [mdc](mdc/index.html) entry as key=value pairs
+    /// * {mdc.key} - The mdc value of the given key, empty if not set
+    /// * {scope} - The names of the current [scopes](fn.scope.html) joined by ">"
+    /// * {elapsed} - Seconds since the first logger was created, from a monotonic clock
+    /// * {delta} - Seconds since the previous message from this logger
+    /// * {uptime_ms} - Milliseconds since the process started
     ///
     /// Note that the names of the placeholders come from the corresponding 
     /// definitions in [log::Record](https://docs.rs/log/0.4.4/log/struct.Record.html).
@@ -96,40 +332,109 @@ where T: Write + Sync + Send + 'static {
     /// The default format is:
     /// [{timestamp}] {level} [{file}:{line}] {args}
     /// 
-    pub fn msg_format(&mut self, format: &'static str) -> &mut Self {
+    pub fn msg_format(&mut self, format: &str) -> &mut Self {
         self.log_formatter.msg_format(format);
         self
     }
+
+    /// Writes an event captured earlier, e.g. from a queue or buffer,
+    /// using the event's own timestamp. Applies the same level
+    /// filtering as log().
+    ///
+    /// # Example
+    /// ```
+    /// use log::{Level,LevelFilter,Record};
+    /// use poly_logger::{GenLogger,LogEvent};
+    ///
+    /// let event = LogEvent::from(&Record::builder()
+    ///     .args(format_args!("Deferred"))
+    ///     .level(Level::Info)
+    ///     .build());
+    ///
+    /// let mut logger = GenLogger::new(LevelFilter::Info, Vec::new());
+    /// logger.msg_format("{level} {timestamp} {args}")
+    ///       .timestamp_format("%H:%M:%S%.6f");
+    /// let stats = logger.stats();
+    /// logger.log_event(&event);
+    ///
+    /// // Written with the time the event was created
+    /// let line = format!("INFO {} Deferred\n", event.timestamp.format("%H:%M:%S%.6f"));
+    /// assert_eq!(stats.snapshot().written(Level::Info), 1);
+    /// assert_eq!(stats.snapshot().bytes, line.len() as u64);
+    /// assert_eq!(logger.log_formatter.event_msg(&event).unwrap() + "\n", line);
+    /// ```
+    pub fn log_event(&self, event: &LogEvent) {
+        if event.level <= self.level_filter_for(&event.target) {
+            self.write_event(event);
+        } else {
+            self.stats.filtered();
+        }
+    }
+
+    /// Returns the counts of records written, filtered and lost by
+    /// this logger. The stats are shared, so they can still be read
+    /// after this logger has been moved by init() or into a PolyLogger.
+    pub fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+
+    fn write_event(&self, event: &dyn EventFields) {
+        let msg = match self.log_formatter.fields_msg(event) {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("Invalid log format: {}", e);
+                self.stats.format_error();
+                self.log_formatter.default_fields_msg(event)
+            },
+        };
+
+        // Note: may want option to not include newline
+        let line = msg + "\n";
+        let mut w = self.writer.lock().unwrap();
+        match w.write_all(line.as_bytes()) {
+            Ok(()) => self.stats.written(event.level(), line.len()),
+            Err(e) => {
+                eprintln!("Failed to write log record: {}", e);
+                self.stats.write_error();
+                self.stats.dropped(1);
+            },
+        }
+    }
 }
 
 // Logger interface
 impl<T> log::Log for GenLogger<T>
 where T: Write + Sync + Send + 'static {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
-        metadata.level() <= self.level_filter
+        let level_filter = match sys::probing() {
+            true => self.max_level_filter(),
+            false => self.level_filter_for(metadata.target()),
+        };
+        metadata.level() <= level_filter
     }
 
     fn log(&self, record: &log::Record) {
         if !self.enabled(record.metadata()) {
+            self.stats.filtered();
             return;
         }
 
-        let msg = match self.log_formatter.msg(record) {
-            Ok(m) => m,
-            Err(e) => {
-                eprintln!("Invalid log format: {}", e); 
-                self.log_formatter.default_msg(record)
-            },
-        };
-
-        // Note: may want option to not include newline
-        let mut w = self.writer.lock().unwrap();
-        w.write_all((msg + "\n").as_bytes()).unwrap();
+        let _in_logger = sys::enter_logger();
+        self.write_event(&RecordEvent::new(record));
     }
 
     fn flush(&self) { 
         let mut w = self.writer.lock().unwrap();
-        w.flush().unwrap();
+        if let Err(e) = w.flush() {
+            eprintln!("Failed to flush log records: {}", e);
+            self.stats.write_error();
+        }
     }
 }
 
+impl<T> CountedLog for GenLogger<T>
+where T: Write + Sync + Send + 'static {
+    fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+}
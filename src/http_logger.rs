@@ -0,0 +1,650 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, OnceLock};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use log::{Level, LevelFilter, SetLoggerError};
+use super::log_formatter::{LogFormatter, LogFormat};
+use super::log_event::{LogEvent, RecordEvent, EventFields};
+use super::flush_guard;
+use super::stats::{CountedLog, LoggerStats};
+use super::sys;
+
+/// Implements a logger which POSTs batches of JSON records to
+/// an HTTP log ingestion endpoint
+///
+/// Records are formatted as JSON (see
+/// [LogFormat::Json](enum.LogFormat.html)) in the logging thread and
+/// queued for a background thread, so log() never waits on the network.
+/// The background thread sends a batch when it reaches batch_size()
+/// records or batch_bytes(), or when the oldest record in the batch is
+/// batch_interval() old. Failed requests are retried with exponential
+/// backoff. If the queue is full, new records are dropped and counted.
+///
+/// Only plain http:// URLs are supported. An IPv6 host is written
+/// in brackets, e.g. http://[::1]:8080/ingest.
+///
+/// # Example
+/// Sends a batch to a local stub server
+/// ```
+/// use std::io::{BufRead,BufReader,Read,Write};
+/// use std::net::TcpListener;
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::HttpLogger;
+///
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+/// let server = std::thread::spawn(move || {
+///     let (stream, _) = listener.accept().unwrap();
+///     let mut reader = BufReader::new(stream);
+///     let mut request = String::new();
+///     let mut length = 0;
+///     loop {
+///         let mut line = String::new();
+///         reader.read_line(&mut line).unwrap();
+///         if let Some(len) = line.strip_prefix("Content-Length: ") {
+///             length = len.trim().parse().unwrap();
+///         }
+///         request.push_str(&line);
+///         if line == "\r\n" { break; }
+///     }
+///     let mut body = vec![0u8; length];
+///     reader.read_exact(&mut body).unwrap();
+///     reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+///     (request, String::from_utf8(body).unwrap())
+/// });
+///
+/// let mut logger = HttpLogger::new(LevelFilter::Info, &url).unwrap();
+/// logger.timestamp_format("")
+///       .header("X-Api-Key", "secret")
+///       .header("X-Trace", "1\r\nX-Admin: true"); // Ignored
+/// for msg in ["one", "two"].iter() {
+///     logger.log(&Record::builder()
+///         .args(format_args!("{}", msg))
+///         .level(Level::Info)
+///         .target("app")
+///         .build());
+/// }
+/// logger.flush();
+///
+/// let (request, body) = server.join().unwrap();
+/// assert!(request.starts_with("POST /ingest HTTP/1.1\r\n"));
+/// assert!(request.contains("X-Api-Key: secret\r\n"));
+/// assert!(!request.contains("X-Admin"));
+/// assert_eq!(body, concat!(
+///     r#"[{"level":"INFO","target":"app","file":"<no_file>","line":0,"message":"one"},"#,
+///     r#"{"level":"INFO","target":"app","file":"<no_file>","line":0,"message":"two"}]"#));
+/// ```
+///
+/// # IPv6 Example
+/// ```
+/// use std::io::{BufRead,BufReader,Read,Write};
+/// use std::net::TcpListener;
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::HttpLogger;
+///
+/// let listener = TcpListener::bind("[::1]:0").unwrap();
+/// let port = listener.local_addr().unwrap().port();
+/// let server = std::thread::spawn(move || {
+///     let (stream, _) = listener.accept().unwrap();
+///     let mut reader = BufReader::new(stream);
+///     let mut headers = Vec::new();
+///     loop {
+///         let mut line = String::new();
+///         reader.read_line(&mut line).unwrap();
+///         if line == "\r\n" { break; }
+///         headers.push(line);
+///     }
+///     let length: usize = headers.iter()
+///         .find_map(|h| h.strip_prefix("Content-Length: "))
+///         .map(|len| len.trim().parse().unwrap())
+///         .unwrap();
+///     reader.read_exact(&mut vec![0u8; length]).unwrap();
+///     reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+///     headers
+/// });
+///
+/// let logger = HttpLogger::new(LevelFilter::Info, &format!("http://[::1]:{}/", port)).unwrap();
+/// logger.log(&Record::builder()
+///     .args(format_args!("Over IPv6"))
+///     .level(Level::Info)
+///     .build());
+/// logger.flush();
+///
+/// let headers = server.join().unwrap();
+/// assert!(headers.contains(&format!("Host: [::1]:{}\r\n", port)));
+/// ```
+///
+pub struct HttpLogger {
+    level_filter: LevelFilter,
+    log_formatter: LogFormatter,
+    config: HttpConfig,
+    queue: OnceLock<Arc<SyncSender<Msg>>>,
+    stats: Arc<LoggerStats>,
+}
+
+/// The shape of the request body sent by an
+/// [HttpLogger](struct.HttpLogger.html)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HttpPayload {
+    /// A JSON array of records, as accepted by most webhooks
+    JsonArray,
+    /// Newline delimited JSON records
+    NdJson,
+    /// An Elasticsearch _bulk request indexing each record
+    /// into the given index, or the index in the URL if None
+    ElasticsearchBulk(Option<String>),
+    /// A Loki push request with a single stream with the given labels
+    Loki(Vec<(String, String)>),
+}
+
+// Settings used by the background thread
+#[derive(Clone)]
+struct HttpConfig {
+    host: String,
+    port: u16,
+    path: String,
+    headers: Vec<(String, String)>,
+    payload: HttpPayload,
+    gzip: bool,
+    batch_size: usize,
+    batch_bytes: usize,
+    batch_interval: Duration,
+    queue_size: usize,
+    max_retries: u32,
+    backoff: Duration,
+    timeout: Duration,
+    flush_timeout: Duration,
+}
+
+// A formatted record and when it was logged
+struct Entry {
+    nanos: u128,
+    level: Level,
+    json: String,
+}
+
+enum Msg {
+    Record(Entry),
+    Flush(mpsc::Sender<()>),
+}
+
+impl HttpLogger {
+    /// Instantiates an HttpLogger which posts to the given URL,
+    /// which must be of the form http://host[:port][/path]
+    pub fn new(level_filter: LevelFilter, url: &str) -> io::Result<HttpLogger> {
+        let (host, port, path) = parse_url(url)?;
+        let mut log_formatter = LogFormatter::new();
+        log_formatter.format(LogFormat::Json);
+
+        Ok(HttpLogger {
+            level_filter,
+            log_formatter,
+            config: HttpConfig {
+                host,
+                port,
+                path,
+                headers: Vec::new(),
+                payload: HttpPayload::JsonArray,
+                gzip: false,
+                batch_size: 100,
+                batch_bytes: 1024 * 1024,
+                batch_interval: Duration::from_secs(1),
+                queue_size: 10_000,
+                max_retries: 3,
+                backoff: Duration::from_millis(500),
+                timeout: Duration::from_secs(10),
+                flush_timeout: Duration::from_secs(5),
+            },
+            queue: OnceLock::new(),
+            stats: Arc::new(LoggerStats::new()),
+        })
+    }
+
+    /// Initializes the log interface using this HttpLogger
+    /// as a boxed logger. This moves self so is the last
+    /// method to call on this object.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        log::set_max_level(self.level_filter);
+        log::set_boxed_logger(Box::new(self))
+    }
+
+    /// Sets the timestamp format of the JSON records.
+    /// See [GenLogger::timestamp_format](struct.GenLogger.html#method.timestamp_format)
+    pub fn timestamp_format(&mut self, format: &str) -> &mut Self {
+        self.log_formatter.timestamp_format(format);
+        self
+    }
+
+    /// Sets the request body format. The default is HttpPayload::JsonArray
+    pub fn payload(&mut self, payload: HttpPayload) -> &mut Self {
+        self.config.payload = payload;
+        self
+    }
+
+    /// Adds a header to each request, e.g. for authorization. A
+    /// header whose name is not a valid token, or whose value contains
+    /// a line break or other control character, is reported on stderr
+    /// and ignored, as it could inject headers into the request.
+    pub fn header(&mut self, name: &str, value: &str) -> &mut Self {
+        let valid_name = !name.is_empty() &&
+            name.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c));
+        let valid_value = value.chars().all(|c| c == '\t' || !c.is_control());
+        match valid_name && valid_value {
+            true => self.config.headers.push((name.to_string(), value.to_string())),
+            false => eprintln!("Invalid HTTP header ignored: {:?}", name),
+        }
+        self
+    }
+
+    /// Compresses request bodies with gzip
+    pub fn gzip(&mut self, gzip: bool) -> &mut Self {
+        self.config.gzip = gzip;
+        self
+    }
+
+    /// Sets the most records sent in one request. The default is 100
+    pub fn batch_size(&mut self, records: usize) -> &mut Self {
+        self.config.batch_size = records.max(1);
+        self
+    }
+
+    /// Sets the size in bytes of the formatted records at which a
+    /// batch is sent. The default is 1MiB.
+    pub fn batch_bytes(&mut self, bytes: usize) -> &mut Self {
+        self.config.batch_bytes = bytes;
+        self
+    }
+
+    /// Sets the longest a record waits before its batch is sent.
+    /// The default is 1s.
+    pub fn batch_interval(&mut self, interval: Duration) -> &mut Self {
+        self.config.batch_interval = interval;
+        self
+    }
+
+    /// Sets the number of records queued for the background thread
+    /// before new records are dropped. The default is 10000.
+    pub fn queue_size(&mut self, records: usize) -> &mut Self {
+        self.config.queue_size = records.max(1);
+        self
+    }
+
+    /// Sets how often a failed request is retried, and the delay
+    /// before the first retry which doubles on each retry.
+    /// The default is 3 retries starting at 500ms.
+    pub fn retries(&mut self, max_retries: u32, backoff: Duration) -> &mut Self {
+        self.config.max_retries = max_retries;
+        self.config.backoff = backoff;
+        self
+    }
+
+    /// Sets the connect, read and write timeout of each request.
+    /// The default is 10s.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.config.timeout = timeout;
+        self
+    }
+
+    /// Sets how long flush() waits for queued records to be sent.
+    /// The default is 5s.
+    pub fn flush_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.config.flush_timeout = timeout;
+        self
+    }
+
+    /// Returns the counts of records sent, records lost because the
+    /// queue was full or a batch could not be sent (as dropped), and
+    /// records waiting in the queue. The stats are shared, so they can still
+    /// be read after this logger has been moved.
+    pub fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+
+    /// Queues an event captured earlier, e.g. from a queue, using the
+    /// event's own timestamp. Applies the same level filtering as log().
+    pub fn log_event(&self, event: &LogEvent) {
+        if event.level <= self.level_filter {
+            self.queue_event(event);
+        } else {
+            self.stats.filtered();
+        }
+    }
+
+    fn queue_event(&self, event: &dyn EventFields) {
+        let json = match self.log_formatter.fields_msg(event) {
+            Ok(m) => m,
+            Err(_) => {
+                self.stats.format_error();
+                self.log_formatter.default_fields_msg(event)
+            },
+        };
+        let nanos = event.timestamp().timestamp_nanos_opt().unwrap_or(0).max(0) as u128;
+
+        let level = event.level();
+        // Counted before sending, as the thread may dequeue it at once
+        self.stats.queued();
+        match self.queue().try_send(Msg::Record(Entry{nanos, level, json})) {
+            Ok(_) => {},
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.stats.dequeued(1);
+                self.stats.dropped(1);
+            },
+        }
+    }
+
+    // Starts the background thread on first use, so that
+    // all settings are in place
+    fn queue(&self) -> &SyncSender<Msg> {
+        self.queue.get_or_init(|| {
+            let (sender, receiver) = mpsc::sync_channel(self.config.queue_size);
+            let config = self.config.clone();
+            let stats = self.stats.clone();
+            thread::Builder::new()
+                .name("poly_logger_http".to_string())
+                .spawn(move || run(config, receiver, stats))
+                .expect("Failed to spawn HttpLogger thread");
+
+            // Drain the queue on shutdown, for as long as this logger lives
+            let sender = Arc::new(sender);
+            let weak = Arc::downgrade(&sender);
+            flush_guard::on_shutdown(move |deadline| {
+                match weak.upgrade() {
+                    Some(queue) => {
+                        flush_queue(&queue, deadline);
+                        true
+                    },
+                    None => false,
+                }
+            });
+            sender
+        })
+    }
+}
+
+// The background thread
+fn run(config: HttpConfig, receiver: Receiver<Msg>, stats: Arc<LoggerStats>) {
+    let mut batch: Vec<Entry> = Vec::new();
+    let mut bytes = 0;
+    let mut deadline = Instant::now();
+
+    loop {
+        let msg = match batch.is_empty() {
+            true => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            false => receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+        };
+
+        match msg {
+            Ok(Msg::Record(entry)) => {
+                stats.dequeued(1);
+                if batch.is_empty() {
+                    deadline = Instant::now() + config.batch_interval;
+                }
+                bytes += entry.json.len();
+                batch.push(entry);
+                if batch.len() >= config.batch_size || bytes >= config.batch_bytes {
+                    send_batch(&config, &mut batch, &stats);
+                    bytes = 0;
+                }
+            },
+            Ok(Msg::Flush(ack)) => {
+                send_batch(&config, &mut batch, &stats);
+                bytes = 0;
+                let _ = ack.send(());
+            },
+            Err(RecvTimeoutError::Timeout) => {
+                send_batch(&config, &mut batch, &stats);
+                bytes = 0;
+            },
+            Err(RecvTimeoutError::Disconnected) => {
+                send_batch(&config, &mut batch, &stats);
+                break;
+            },
+        }
+    }
+}
+
+fn send_batch(config: &HttpConfig, batch: &mut Vec<Entry>, stats: &LoggerStats) {
+    if batch.is_empty() {
+        return;
+    }
+
+    let (content_type, body) = payload(&config.payload, batch);
+    let result = gzip(config.gzip, body).and_then(|body| {
+        let mut backoff = config.backoff;
+        let mut retries = 0;
+        loop {
+            match post(config, content_type, &body) {
+                Err(e) if retries < config.max_retries && is_retryable(&e) => {
+                    retries += 1;
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                },
+                result => break result,
+            }
+        }
+    });
+
+    match result {
+        Ok(()) => {
+            for entry in batch.iter() {
+                stats.written(entry.level, entry.json.len());
+            }
+        },
+        Err(e) => {
+            eprintln!("HttpLogger failed to send {} records: {}", batch.len(), e);
+            stats.write_error();
+            stats.dropped(batch.len() as u64);
+        },
+    }
+    batch.clear();
+}
+
+fn gzip(gzip: bool, body: String) -> io::Result<Vec<u8>> {
+    match gzip {
+        true => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body.as_bytes())?;
+            encoder.finish()
+        },
+        false => Ok(body.into_bytes()),
+    }
+}
+
+// Renders the request body for a batch
+fn payload(payload: &HttpPayload, batch: &[Entry]) -> (&'static str, String) {
+    match payload {
+        HttpPayload::JsonArray => {
+            let records: Vec<&str> = batch.iter().map(|e| e.json.as_str()).collect();
+            ("application/json", format!("[{}]", records.join(",")))
+        },
+        HttpPayload::NdJson => {
+            let mut body = String::new();
+            for entry in batch {
+                body.push_str(&entry.json);
+                body.push('\n');
+            }
+            ("application/x-ndjson", body)
+        },
+        HttpPayload::ElasticsearchBulk(index) => {
+            let action = match index {
+                Some(index) => serde_json::json!({"index": {"_index": index}}).to_string(),
+                None => r#"{"index":{}}"#.to_string(),
+            };
+            let mut body = String::new();
+            for entry in batch {
+                body.push_str(&action);
+                body.push('\n');
+                body.push_str(&entry.json);
+                body.push('\n');
+            }
+            ("application/x-ndjson", body)
+        },
+        HttpPayload::Loki(labels) => {
+            let stream: serde_json::Map<_, _> = labels.iter()
+                .map(|(k, v)| (k.clone(), v.clone().into()))
+                .collect();
+            let values: Vec<_> = batch.iter()
+                .map(|e| serde_json::json!([e.nanos.to_string(), e.json]))
+                .collect();
+            let body = serde_json::json!({"streams": [{"stream": stream, "values": values}]});
+            ("application/json", body.to_string())
+        },
+    }
+}
+
+// An error from the server which is worth retrying
+#[derive(Debug)]
+struct HttpStatus(u16);
+
+impl std::fmt::Display for HttpStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "HTTP status {}", self.0)
+    }
+}
+
+impl std::error::Error for HttpStatus {}
+
+fn is_retryable(e: &io::Error) -> bool {
+    match e.get_ref().and_then(|inner| inner.downcast_ref::<HttpStatus>()) {
+        Some(HttpStatus(status)) => *status == 429 || *status >= 500,
+        None => true,
+    }
+}
+
+// Sends a single HTTP/1.1 POST request and checks the response status
+fn post(config: &HttpConfig, content_type: &str, body: &[u8]) -> io::Result<()> {
+    let addr = (config.host.as_str(), config.port).to_socket_addrs()?.next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Cannot resolve host"))?;
+    let mut stream = TcpStream::connect_timeout(&addr, config.timeout)?;
+    stream.set_read_timeout(Some(config.timeout))?;
+    stream.set_write_timeout(Some(config.timeout))?;
+
+    // IPv6 addresses are bracketed in the Host header
+    let host = match config.host.contains(':') {
+        true => format!("[{}]", config.host),
+        false => config.host.clone(),
+    };
+    let mut request = format!("POST {} HTTP/1.1\r\n\
+                               Host: {}:{}\r\n\
+                               Content-Type: {}\r\n\
+                               Content-Length: {}\r\n\
+                               Connection: close\r\n",
+                              config.path, host, config.port,
+                              content_type, body.len());
+    if config.gzip {
+        request.push_str("Content-Encoding: gzip\r\n");
+    }
+    for (name, value) in &config.headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    // e.g. "HTTP/1.1 204 No Content"
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    let status = status_line.split_whitespace().nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("Invalid HTTP response: {:?}", status_line)))?;
+    match status {
+        200..=299 => Ok(()),
+        _ => Err(io::Error::other(HttpStatus(status))),
+    }
+}
+
+// Splits http://host[:port][/path] into its parts. An IPv6
+// host such as [::1] is returned without its brackets.
+fn parse_url(url: &str) -> io::Result<(String, u16, String)> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput,
+                                             format!("{}: {}", msg, url));
+    let rest = url.strip_prefix("http://")
+        .ok_or_else(|| invalid("Only http:// URLs are supported"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, after) = bracketed.split_once(']')
+                .ok_or_else(|| invalid("Unclosed [ in URL"))?;
+            match after {
+                "" => (host, None),
+                _ => match after.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None => return Err(invalid("Invalid port in URL")),
+                },
+            }
+        },
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| invalid("Invalid port in URL"))?,
+        None => 80,
+    };
+    if host.is_empty() {
+        return Err(invalid("No host in URL"));
+    }
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+impl log::Log for HttpLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level_filter
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            self.stats.filtered();
+            return;
+        }
+
+        let _in_logger = sys::enter_logger();
+        self.queue_event(&RecordEvent::new(record));
+    }
+
+    // Waits up to the flush_timeout, or the end of a shutdown,
+    // for queued records to be sent
+    fn flush(&self) {
+        let queue = match self.queue.get() {
+            Some(queue) => queue,
+            None => return,
+        };
+        flush_queue(queue, flush_guard::flush_deadline(self.config.flush_timeout));
+    }
+}
+
+impl CountedLog for HttpLogger {
+    fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+}
+
+// Waits until the deadline for the background thread to send
+// everything queued so far. The queue may be full, so the flush
+// request itself is only queued if there is room in time.
+fn flush_queue(queue: &SyncSender<Msg>, deadline: Instant) {
+    let (ack, done) = mpsc::channel();
+    let mut msg = Msg::Flush(ack);
+    loop {
+        match queue.try_send(msg) {
+            Ok(()) => break,
+            Err(TrySendError::Full(m)) if Instant::now() < deadline => {
+                msg = m;
+                thread::sleep(Duration::from_millis(1));
+            },
+            Err(_) => return,
+        }
+    }
+    let _ = done.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+}
@@ -1,8 +1,11 @@
+use std::io::{self, IsTerminal};
 use std::path::Path;
 use std::fs::{File, OpenOptions};
 use log::{LevelFilter, SetLoggerError};
 use super::gen_logger::{GenLogger};
-use super::log_formatter::LogFormatter;
+use super::log_formatter::{LogFormatter, LogFormat, Escape};
+use super::env::EnvOverrides;
+use super::flush_guard::FlushGuard;
 
 /// Implements a GenLogger that outputs to Stdout
 ///
@@ -42,8 +45,13 @@ use super::log_formatter::LogFormatter;
 pub struct StdoutLogger {}
 
 impl StdoutLogger {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(level_filter: LevelFilter) -> GenLogger<std::io::Stdout> {
-        GenLogger::new(level_filter, std::io::stdout())
+        let stdout = std::io::stdout();
+        let is_terminal = stdout.is_terminal();
+        let mut logger = GenLogger::new(level_filter, stdout);
+        logger.is_terminal(is_terminal);
+        logger
     }
 }
 
@@ -85,8 +93,13 @@ impl StdoutLogger {
 pub struct StderrLogger {}
 
 impl StderrLogger {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(level_filter: LevelFilter) -> GenLogger<std::io::Stderr> {
-        GenLogger::new(level_filter, std::io::stderr())
+        let stderr = std::io::stderr();
+        let is_terminal = stderr.is_terminal();
+        let mut logger = GenLogger::new(level_filter, stderr);
+        logger.is_terminal(is_terminal);
+        logger
     }
 }
 
@@ -127,6 +140,11 @@ impl StderrLogger {
 /// info!("This is an INFO message with custom formatting");
 /// ```
 ///
+/// # Escaping
+/// Unlike the other loggers, a FileLogger escapes control characters
+/// in messages by default, so that user input containing newlines
+/// cannot forge log lines. See escape().
+///
 /// # Note
 /// The call to new() is actually returning an instance of GenLogger<Stderr>.
 /// The StderrLogger struct is just a way to instantiate a GenLogger 
@@ -138,6 +156,7 @@ pub struct FileLogger {
     level_filter: LevelFilter,
     truncate: bool,
     filename: Option<String>,
+    target_levels: Vec<(String, LevelFilter)>,
     log_formatter: LogFormatter,
 }
 
@@ -148,36 +167,67 @@ impl FileLogger {
     /// A call to create() returns the GenLogger<File> instance
     /// we need, though this is typically done by calling init().
     pub fn new(level_filter: LevelFilter) -> FileLogger {
+        let mut log_formatter = LogFormatter::new();
+        log_formatter.escape(Escape::Escape);
         FileLogger { 
             level_filter, 
             truncate: false,
             filename: None,
-            log_formatter: LogFormatter::new()
+            target_levels: Vec::new(),
+            log_formatter,
         }
     }
 
     /// Calls create() to get the GenLogger instance 
     /// which is then in turn initialized. 
     pub fn init(&self) -> Result<(), SetLoggerError> {
-        let logger = self.create();
-        log::set_max_level(self.level_filter);
-        log::set_boxed_logger(Box::new(logger))
+        self.create().init()
+    }
+
+    /// Creates the underlying GenLogger and initializes the log
+    /// interface with it, returning a [FlushGuard](struct.FlushGuard.html)
+    /// which flushes the file when dropped
+    pub fn init_with_guard(&self) -> Result<FlushGuard, SetLoggerError> {
+        self.create().init_with_guard()
+    }
+
+    /// Sets the output format for the underlying
+    /// [GenLogger](struct.GenLogger.html) instance
+    pub fn log_format(&mut self, format: LogFormat) -> &mut Self {
+        self.log_formatter.format(format);
+        self
     }
 
     /// Sets timestamp format for the underlying 
     /// [GenLogger](struct.GenLogger.html) instance
-    pub fn timestamp_format(&mut self, format: &'static str) -> &mut Self {
+    pub fn timestamp_format(&mut self, format: &str) -> &mut Self {
         self.log_formatter.timestamp_format(format);
         self
     }
 
     /// Sets message format for the underlying 
     /// [GenLogger](struct.GenLogger.html) instance
-    pub fn msg_format(&mut self, format: &'static str) -> &mut Self {
+    pub fn msg_format(&mut self, format: &str) -> &mut Self {
         self.log_formatter.msg_format(format);
         self
     }
 
+    /// Sets how control characters are written by the underlying
+    /// [GenLogger](struct.GenLogger.html) instance. The default
+    /// for a FileLogger is Escape::Escape.
+    pub fn escape(&mut self, escape: Escape) -> &mut Self {
+        self.log_formatter.escape(escape);
+        self
+    }
+
+    /// Sets a target level filter for the underlying
+    /// [GenLogger](struct.GenLogger.html) instance
+    pub fn target_level(&mut self, target: &str, level_filter: LevelFilter) -> &mut Self {
+        self.target_levels.retain(|(t, _)| t != target);
+        self.target_levels.push((target.to_string(), level_filter));
+        self
+    }
+
     /// Truncates log file before writing. Default is to append
     pub fn truncate(&mut self, truncate: bool) -> &mut Self {
         self.truncate = truncate;
@@ -185,43 +235,88 @@ impl FileLogger {
     }
 
     /// Sets log file name
-    pub fn filename(&mut self, filename: &'static str) -> &mut Self {
+    pub fn filename(&mut self, filename: &str) -> &mut Self {
         self.filename = Some(filename.to_string());
         self
     }
 
+    /// Overrides settings from environment variables with the given
+    /// prefix, as for [GenLogger::env_overrides](struct.GenLogger.html#method.env_overrides).
+    /// FileLogger also reads the following, e.g. for a prefix of "MYAPP":
+    /// * MYAPP_LOG_FILE - see filename()
+    /// * MYAPP_LOG_TRUNCATE - "true" or "false", see truncate()
+    ///
+    /// # Example
+    /// ```
+    /// use log::{info,LevelFilter};
+    /// use poly_logger::FileLogger;
+    ///
+    /// std::env::set_var("MYAPP_LOG_FILE", "./test.log");
+    ///
+    /// let mut logger = FileLogger::new(LevelFilter::Info);
+    /// logger.filename("/var/log/myapp.log")
+    ///       .env_overrides("MYAPP");
+    /// logger.init().unwrap();
+    /// info!("This goes to ./test.log");
+    /// ```
+    pub fn env_overrides(&mut self, prefix: &str) -> &mut Self {
+        let overrides = EnvOverrides::read(prefix);
+        overrides.apply(&mut self.level_filter, &mut self.log_formatter);
+        if let Some(filename) = &overrides.file {
+            self.filename(filename);
+        }
+        if let Some(truncate) = overrides.truncate {
+            self.truncate(truncate);
+        }
+        self
+    }
+
     /// We need to call this to get a Log interface 
     /// object such as when  passing to PolyLogger.
     /// If this is a standalone logger, create() will be
     /// called when do the init().
+    ///
+    /// Panics if no filename was given or the file cannot
+    /// be opened. See try_create() for a non-panicking version.
     pub fn create(&self) -> GenLogger<File> {
-        match &self.filename {
-            Some(filename) => {
-                // Create the file parent directory
-                let path = Path::new(&filename);
-                let parent = path.parent().unwrap();
-                std::fs::create_dir_all(parent).unwrap();
-
-                // Create the log file with our truncate option
-                let mut file = OpenOptions::new();
-                let file = match &self.truncate {
-                    true => file.write(true).truncate(true),
-                    false => file.append(true),
-                };
-                let file = file.create(true)
-                    .open(path)
-                    .unwrap();
-
-                // Create the GenLogger and pass in the log_formatter
-                let mut logger = GenLogger::new(self.level_filter, file);
-                //logger.set_log_formatter(&self.log_formatter);
-                logger.log_formatter = self.log_formatter.clone();
-                logger
+        match self.try_create() {
+            Ok(logger) => logger,
+            Err(e) => panic!("Failed to create FileLogger: {}", e),
+        }
+    }
+
+    /// Same as create() but returns an error if no filename
+    /// was given or the log file could not be opened.
+    pub fn try_create(&self) -> io::Result<GenLogger<File>> {
+        let filename = match &self.filename {
+            Some(filename) => filename,
+            None => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          "No filename specified for FileLogger"));
             },
-            _ => {
-                // NOTE - Can we do better than panic?
-                panic!("No filename specified for FileLogger");
-            }
+        };
+
+        // Create the file parent directory
+        let path = Path::new(filename);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // Create the log file with our truncate option
+        let mut file = OpenOptions::new();
+        let file = match &self.truncate {
+            true => file.write(true).truncate(true),
+            false => file.append(true),
+        };
+        let file = file.create(true).open(path)?;
+
+        // Create the GenLogger and pass in the log_formatter
+        let mut logger = GenLogger::new(self.level_filter, file);
+        logger.log_formatter = self.log_formatter.clone();
+        logger.no_color();
+        for (target, level_filter) in &self.target_levels {
+            logger.target_level(target, *level_filter);
         }
+        Ok(logger)
     }
 }
@@ -0,0 +1,368 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use log::{LevelFilter, SetLoggerError};
+use super::log_formatter::LogFormatter;
+use super::syslog_logger::syslog_severity;
+use super::log_event::{LogEvent, RecordEvent, EventFields};
+use super::stats::{CountedLog, LoggerStats};
+use super::sys;
+
+/// The socket of the systemd journal's native protocol
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Implements a logger which writes to the systemd journal
+///
+/// Records are sent over the journal's
+/// [native protocol](https://systemd.io/JOURNAL_NATIVE_PROTOCOL/)
+/// with the fields MESSAGE, PRIORITY, CODE_FILE, CODE_LINE, TARGET
+/// and SYSLOG_IDENTIFIER, plus any key-values in the record with their
+/// keys uppercased (e.g. a key of "user_id" becomes the USER_ID field).
+/// Keys which would name a field the logger or the journal sets
+/// itself, such as "message" or "_pid", are prefixed with KV_ so
+/// that they cannot be spoofed. Entries which are too large for a
+/// datagram are passed to the journal in a sealed memfd instead.
+///
+/// The msg_format applies to the MESSAGE field and defaults to "{args}".
+///
+/// # Example
+/// Sends a record to a local datagram socket standing in for the journal
+/// ```
+/// use std::os::unix::net::UnixDatagram;
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::JournaldLogger;
+///
+/// let path = std::env::temp_dir().join("poly_logger_journald_doc.sock");
+/// let _ = std::fs::remove_file(&path);
+/// let journal = UnixDatagram::bind(&path).unwrap();
+///
+/// let logger = JournaldLogger::with_socket(LevelFilter::Info, &path).unwrap();
+/// let kvs = [("user_id", 42)];
+/// logger.log(&Record::builder()
+///     .args(format_args!("Hello journal"))
+///     .level(Level::Warn)
+///     .target("myapp::db")
+///     .file(Some("src/db.rs"))
+///     .line(Some(7))
+///     .key_values(&kvs)
+///     .build());
+///
+/// let mut buf = [0u8; 1024];
+/// let len = journal.recv(&mut buf).unwrap();
+/// let entry = String::from_utf8_lossy(&buf[..len]);
+/// assert!(entry.contains("MESSAGE=Hello journal\n"));
+/// assert!(entry.contains("PRIORITY=4\n"));
+/// assert!(entry.contains("CODE_FILE=src/db.rs\nCODE_LINE=7\n"));
+/// assert!(entry.contains("TARGET=myapp::db\n"));
+/// assert!(entry.contains("USER_ID=42\n"));
+/// ```
+///
+/// # Oversized Entry Example
+/// The entry arrives as a memfd passed with SCM_RIGHTS
+/// ```
+/// use std::fs::File;
+/// use std::io::{Read,Seek,SeekFrom};
+/// use std::os::unix::io::{AsRawFd,FromRawFd};
+/// use std::os::unix::net::UnixDatagram;
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::JournaldLogger;
+///
+/// let path = std::env::temp_dir().join("poly_logger_journald_memfd_doc.sock");
+/// let _ = std::fs::remove_file(&path);
+/// let journal = UnixDatagram::bind(&path).unwrap();
+///
+/// let logger = JournaldLogger::with_socket(LevelFilter::Info, &path).unwrap();
+/// let big = "x".repeat(4 << 20);
+/// let kvs = [("message", "spoofed"), ("_pid", "1")];
+/// logger.log(&Record::builder()
+///     .args(format_args!("{}", big))
+///     .level(Level::Info)
+///     .key_values(&kvs)
+///     .build());
+///
+/// // Receive the fd, which is the only thing sent
+/// let fd = unsafe {
+///     let mut control = [0u8; 64];
+///     let mut msg: libc::msghdr = std::mem::zeroed();
+///     msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+///     msg.msg_controllen = control.len() as _;
+///     assert_eq!(libc::recvmsg(journal.as_raw_fd(), &mut msg, 0), 0);
+///     let cmsg = libc::CMSG_FIRSTHDR(&msg);
+///     assert_eq!((*cmsg).cmsg_type, libc::SCM_RIGHTS);
+///     std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int)
+/// };
+/// // The memfd is shared with the sender, so read it from the start
+/// let mut file = unsafe { File::from_raw_fd(fd) };
+/// file.seek(SeekFrom::Start(0)).unwrap();
+/// let mut entry = Vec::new();
+/// file.read_to_end(&mut entry).unwrap();
+/// let entry = String::from_utf8_lossy(&entry);
+/// assert!(entry.starts_with(&format!("MESSAGE={}\n", big)));
+/// assert!(entry.contains("\nKV_MESSAGE=spoofed\n"));
+/// assert!(entry.contains("\nKV_PID=1\n"));
+/// std::fs::remove_file(&path).unwrap();
+/// ```
+///
+/// # Standalone Example
+/// ```no_run
+/// use log::{info,LevelFilter};
+/// use poly_logger::JournaldLogger;
+///
+/// let logger = JournaldLogger::new(LevelFilter::Info).unwrap();
+/// logger.init().unwrap();
+/// info!("This is an INFO message to the journal");
+/// ```
+///
+pub struct JournaldLogger {
+    level_filter: LevelFilter,
+    identifier: String,
+    log_formatter: LogFormatter,
+    socket: UnixDatagram,
+    path: PathBuf,
+    stats: Arc<LoggerStats>,
+}
+
+impl JournaldLogger {
+    /// Instantiates a JournaldLogger which writes to the journal
+    pub fn new(level_filter: LevelFilter) -> io::Result<JournaldLogger> {
+        JournaldLogger::with_socket(level_filter, JOURNALD_SOCKET)
+    }
+
+    /// Instantiates a JournaldLogger which writes to the given
+    /// socket rather than the journal's default socket
+    pub fn with_socket<P: AsRef<Path>>(level_filter: LevelFilter, path: P) -> io::Result<JournaldLogger> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(&path)?;
+
+        let mut log_formatter = LogFormatter::new();
+        log_formatter.msg_format("{args}");
+        Ok(JournaldLogger {
+            level_filter,
+            identifier: sys::app_name(),
+            log_formatter,
+            socket,
+            path: path.as_ref().to_path_buf(),
+            stats: Arc::new(LoggerStats::new()),
+        })
+    }
+
+    /// Initializes the log interface using this JournaldLogger
+    /// as a boxed logger. This moves self so is the last
+    /// method to call on this object.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        log::set_max_level(self.level_filter);
+        log::set_boxed_logger(Box::new(self))
+    }
+
+    /// Sets the SYSLOG_IDENTIFIER field. The default
+    /// is the name of the executable.
+    pub fn syslog_identifier(&mut self, identifier: &str) -> &mut Self {
+        self.identifier = identifier.to_string();
+        self
+    }
+
+    /// Sets the format of the MESSAGE field.
+    /// See [GenLogger::msg_format](struct.GenLogger.html#method.msg_format)
+    pub fn msg_format(&mut self, format: &str) -> &mut Self {
+        self.log_formatter.msg_format(format);
+        self
+    }
+
+    /// Sets the timestamp format used by the {timestamp}
+    /// placeholder in the msg_format
+    pub fn timestamp_format(&mut self, format: &str) -> &mut Self {
+        self.log_formatter.timestamp_format(format);
+        self
+    }
+
+    /// Returns the counts of entries written, filtered and lost by
+    /// this logger. The stats are shared, so they can still be read
+    /// after this logger has been moved by init() or into a PolyLogger.
+    pub fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+
+    /// Writes an event captured earlier, e.g. from a queue, using the
+    /// event's own timestamp. Applies the same level filtering as log().
+    pub fn log_event(&self, event: &LogEvent) {
+        if event.level <= self.level_filter {
+            self.write(event);
+        } else {
+            self.stats.filtered();
+        }
+    }
+
+    fn write(&self, event: &dyn EventFields) {
+        let entry = self.entry(event);
+        match self.send(&entry) {
+            Ok(()) => self.stats.written(event.level(), entry.len()),
+            Err(e) => {
+                eprintln!("Failed to write to the journal: {}", e);
+                self.stats.write_error();
+                self.stats.dropped(1);
+            },
+        }
+    }
+
+    // Serializes a record in the journal's native format
+    fn entry(&self, event: &dyn EventFields) -> Vec<u8> {
+        let msg = match self.log_formatter.fields_msg(event) {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("Invalid log format: {}", e);
+                self.stats.format_error();
+                event.message().to_string()
+            },
+        };
+
+        let mut entry = Vec::with_capacity(msg.len() + 256);
+        add_field(&mut entry, "MESSAGE", msg.as_bytes());
+        add_field(&mut entry, "PRIORITY",
+                  syslog_severity(event.level()).to_string().as_bytes());
+        if let Some(file) = event.file() {
+            add_field(&mut entry, "CODE_FILE", file.as_bytes());
+        }
+        if let Some(line) = event.line() {
+            add_field(&mut entry, "CODE_LINE", line.to_string().as_bytes());
+        }
+        if let Some(module) = event.module_path() {
+            add_field(&mut entry, "CODE_MODULE", module.as_bytes());
+        }
+        add_field(&mut entry, "TARGET", event.target().as_bytes());
+        add_field(&mut entry, "SYSLOG_IDENTIFIER", self.identifier.as_bytes());
+        add_field(&mut entry, "SYSLOG_PID", std::process::id().to_string().as_bytes());
+
+        for (key, value) in event.key_values() {
+            add_field(&mut entry, &field_name(key), value.as_bytes());
+        }
+        entry
+    }
+
+    fn send(&self, entry: &[u8]) -> io::Result<()> {
+        match self.socket.send(entry) {
+            Ok(_) => Ok(()),
+            Err(e) if e.raw_os_error() == Some(libc::EMSGSIZE) ||
+                      e.raw_os_error() == Some(libc::ENOBUFS) => {
+                send_memfd(&self.socket, entry)
+            },
+            Err(_) => {
+                // The journal may have restarted
+                self.socket.connect(&self.path)?;
+                self.socket.send(entry).map(|_| ())
+            },
+        }
+    }
+}
+
+// Appends a field, using the binary encoding if the
+// value contains a newline
+fn add_field(entry: &mut Vec<u8>, name: &str, value: &[u8]) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains(&b'\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value);
+    entry.push(b'\n');
+}
+
+// Fields set by this logger, or with a meaning to the journal, which
+// key-values may not set
+const RESERVED_FIELDS: &[&str] = &[
+    "MESSAGE", "MESSAGE_ID", "PRIORITY", "CODE_FILE", "CODE_LINE",
+    "CODE_FUNC", "CODE_MODULE", "TARGET", "ERRNO", "SYSLOG_IDENTIFIER",
+    "SYSLOG_PID", "SYSLOG_FACILITY", "SYSLOG_TIMESTAMP", "SYSLOG_RAW",
+];
+
+// Field names may only contain uppercase letters, digits and
+// underscores, and may not start with a digit or an underscore.
+// Keys which would name a reserved or trusted field (which start
+// with an underscore) are prefixed with KV_.
+fn field_name(key: &str) -> String {
+    let name: String = key.chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect();
+    let trimmed = name.trim_start_matches('_');
+    let name = match trimmed.chars().next() {
+        None => "FIELD".to_string(),
+        Some(c) if c.is_ascii_digit() => format!("F_{}", trimmed),
+        Some(_) if trimmed.len() < name.len() || RESERVED_FIELDS.contains(&trimmed) => {
+            format!("KV_{}", trimmed)
+        },
+        Some(_) => trimmed.to_string(),
+    };
+    name.chars().take(64).collect()
+}
+
+// Passes an oversized entry to the journal in a sealed memfd
+fn send_memfd(socket: &UnixDatagram, entry: &[u8]) -> io::Result<()> {
+    let name = b"poly_logger\0";
+    let fd = unsafe {
+        libc::memfd_create(name.as_ptr() as *const libc::c_char,
+                           libc::MFD_ALLOW_SEALING | libc::MFD_CLOEXEC)
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // The file closes the memfd when dropped
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.write_all(entry)?;
+
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW |
+        libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+    if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Send the memfd with SCM_RIGHTS and no payload
+    unsafe {
+        let fd_size = std::mem::size_of::<libc::c_int>() as libc::c_uint;
+        let mut control = vec![0u8; libc::CMSG_SPACE(fd_size) as usize];
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.len() as _;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fd_size) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::c_int, fd);
+
+        if libc::sendmsg(socket.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+impl log::Log for JournaldLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level_filter
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            self.stats.filtered();
+            return;
+        }
+
+        let _in_logger = sys::enter_logger();
+        self.write(&RecordEvent::new(record));
+    }
+
+    fn flush(&self) {}
+}
+
+impl CountedLog for JournaldLogger {
+    fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+}
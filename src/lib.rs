@@ -17,15 +17,59 @@
 //! [GenLogger](struct.GenLogger.html) which each implement
 //! output to their respective sink types.
 //!
+//! [SyslogLogger](struct.SyslogLogger.html) sends messages to a
+//! local or remote syslog server, and on Linux
+//! [JournaldLogger](struct.JournaldLogger.html) writes to the
+//! systemd journal. [TcpLogger](struct.TcpLogger.html) streams
+//! lines to a remote collector and [UdpLogger](struct.UdpLogger.html)
+//! sends each record as a datagram. [HttpLogger](struct.HttpLogger.html)
+//! posts batches of JSON records to a log ingestion endpoint.
+//! Graylog can be fed GELF records via
+//! [LogFormat::Gelf](enum.LogFormat.html) over TCP or UDP.
+//! [RingBufferLogger](struct.RingBufferLogger.html) keeps recent
+//! records in memory to be dumped when something goes wrong.
+//! [CaptureLogger](struct.CaptureLogger.html) lets tests assert on
+//! what was logged.
+//! [ChannelLogger](struct.ChannelLogger.html) and
+//! [CallbackLogger](struct.CallbackLogger.html) pass each record as an
+//! owned [LogEvent](struct.LogEvent.html) to a channel or a closure.
+//! A LogEvent can be kept, queued or replayed later, unlike a
+//! log::Record which borrows its arguments, and formatted with a
+//! [LogFormatter](struct.LogFormatter.html).
+//!
+//! The [mdc](mdc/index.html) module holds thread-local key-values,
+//! such as a request ID, which are added to every record logged by
+//! the thread, and [scope](fn.scope.html) names nested sections of
+//! work. [PanicHook](struct.PanicHook.html) logs panics through the
+//! installed logger, and the init_with_guard() methods return a
+//! [FlushGuard](struct.FlushGuard.html) which flushes every logger
+//! on exit.
+//!
+//! [FilteredLogger](struct.FilteredLogger.html) passes records through
+//! [Filter](trait.Filter.html) stages such as
+//! [RateLimit](struct.RateLimit.html), [Dedup](struct.Dedup.html) and
+//! [Sampler](struct.Sampler.html) before they reach a logger, and
+//! [Redact](struct.Redact.html) masks secrets before they are formatted.
+//! Loggers count what they write, filter and lose in
+//! [LoggerStats](struct.LoggerStats.html), which a
+//! [StatsReporter](struct.StatsReporter.html) can log periodically.
+//!
 //! [PolyLogger](struct.PolyLogger.html)
 //! is a container for other loggers. For example, you may
 //! want to log certain messages to STDERR in one format, and others
 //! to go to a File in a different format. You simply create a FileLogger, 
 //! a StderrLogger, and then add both to a new PolyLogger.
+//! Children may be named, and a [PolyHandle](struct.PolyHandle.html)
+//! lets you add, remove, replace, enable or disable them after init().
 //!
 //! In each logger class, you call init() to assign the class instance
 //! to be the Log implementor for your application. 
 //!
+//! A PolyLogger tree may also be described in a TOML, YAML or JSON
+//! file and built with [LoggerConfig](struct.LoggerConfig.html).
+//! [ConfigWatcher](struct.ConfigWatcher.html) reloads such a file
+//! when it changes.
+//!
 //! The examples directory provides use cases for each of the classes.
 //!
 //! # Notes
@@ -52,13 +96,55 @@
 //!   language allows it.
 //!
 
-// Private module used in other loggers
+// Private modules used in other loggers
 mod log_formatter;
+pub use log_formatter::{LogFormatter,LogFormat,Escape};
+mod env;
+mod sys;
 
 // Import our loggers module
 mod poly_logger;
-pub use crate::poly_logger::PolyLogger;
+pub use crate::poly_logger::{PolyLogger,PolyHandle};
 mod gen_logger;
 pub use gen_logger::GenLogger;
 mod instance;
 pub use instance::{StdoutLogger,StderrLogger,FileLogger};
+mod syslog_logger;
+pub use syslog_logger::{SyslogLogger,SyslogFormat,Facility,syslog_severity};
+#[cfg(target_os = "linux")]
+mod journald_logger;
+#[cfg(target_os = "linux")]
+pub use journald_logger::{JournaldLogger,JOURNALD_SOCKET};
+mod tcp_logger;
+pub use tcp_logger::TcpLogger;
+mod udp_logger;
+pub use udp_logger::{UdpLogger,Oversize,GelfCompression};
+mod http_logger;
+pub use http_logger::{HttpLogger,HttpPayload};
+mod ring_buffer_logger;
+pub use ring_buffer_logger::{RingBufferLogger,RingBufferHandle};
+mod capture_logger;
+pub use capture_logger::{CaptureLogger,CapturedRecord,Capture};
+mod log_event;
+pub use log_event::LogEvent;
+pub mod mdc;
+mod scope;
+pub use scope::{scope,set_scope_level,Scope};
+mod channel_logger;
+pub use channel_logger::{ChannelLogger,CallbackLogger,EventSender};
+mod filter;
+pub use filter::{Filter,FilteredLogger,RateLimit,RateLimitKey,Dedup,Sampler};
+mod redact;
+pub use redact::{Redact,Detector};
+mod flush_guard;
+pub use flush_guard::{FlushGuard,set_exit_timeout};
+mod stats;
+pub use stats::{CountedLog,LoggerStats,StatsSnapshot,StatsReporter};
+mod panic_hook;
+pub use panic_hook::PanicHook;
+mod color;
+pub use color::{ColorMode,ColorPalette};
+mod config;
+pub use config::{LoggerConfig,ChildConfig,LoggerKind,ConfigFormat,ConfigError};
+mod config_watcher;
+pub use config_watcher::ConfigWatcher;
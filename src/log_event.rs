@@ -0,0 +1,226 @@
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::thread::{self, ThreadId};
+use std::time::Instant;
+use chrono::{DateTime, Local};
+use log::Level;
+use super::{mdc, scope, sys};
+
+/// An owned snapshot of a log record, which unlike a
+/// [log::Record](https://docs.rs/log/latest/log/struct.Record.html)
+/// can be kept, queued or sent to another thread
+///
+/// The timestamp and thread are captured when the event is created
+/// from a record, and the message is the record's arguments rendered
+/// as a string. Events can be written later with the original
+/// timestamp by the log_event() method of the crate's loggers, such as
+/// [GenLogger::log_event](struct.GenLogger.html#method.log_event),
+/// formatted with a [LogFormatter](struct.LogFormatter.html), or
+/// replayed into any logger with replay().
+///
+/// # Example
+/// ```
+/// use log::{Level,LevelFilter,Record};
+/// use poly_logger::{CaptureLogger,LogEvent};
+///
+/// let kvs = [("user", "dave")];
+/// let event = LogEvent::from(&Record::builder()
+///     .args(format_args!("Hello {}", "event"))
+///     .level(Level::Info)
+///     .target("app")
+///     .module_path(Some("app::main"))
+///     .key_values(&kvs)
+///     .build());
+/// assert_eq!(event.message, "Hello event");
+/// assert_eq!(event.module_path.as_deref(), Some("app::main"));
+/// assert_eq!(event.thread_id, std::thread::current().id());
+///
+/// // Replay it later into another logger
+/// let logger = CaptureLogger::new(LevelFilter::Info);
+/// let capture = logger.handle();
+/// event.replay(&logger);
+/// assert_eq!(capture.messages(), vec!["Hello event"]);
+/// assert_eq!(capture.records()[0].key_values, event.key_values);
+/// ```
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogEvent {
+    pub timestamp: DateTime<Local>,
+    /// A monotonic timestamp, taken at the same time as timestamp
+    pub instant: Instant,
+    pub level: Level,
+    pub target: String,
+    pub module_path: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub message: String,
+    pub key_values: Vec<(String, String)>,
+    /// The thread's [mdc](mdc/index.html) when the event was created
+    pub mdc: Vec<(String, String)>,
+    /// The thread's [scopes](fn.scope.html), outermost first
+    pub scope: Vec<String>,
+    pub thread_id: ThreadId,
+    pub thread_name: Option<String>,
+}
+
+impl LogEvent {
+    /// Passes this event to a logger as a record, with the event's
+    /// mdc in place. The logger timestamps the record itself.
+    pub fn replay(&self, logger: &dyn log::Log) {
+        let _guards: Vec<_> = self.mdc.iter()
+            .map(|(k, v)| mdc::scoped(k, v))
+            .collect();
+        let kvs: Vec<(&str, &str)> = self.key_values.iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        logger.log(&log::Record::builder()
+            .args(format_args!("{}", self.message))
+            .level(self.level)
+            .target(&self.target)
+            .module_path(self.module_path.as_deref())
+            .file(self.file.as_deref())
+            .line(self.line)
+            .key_values(&kvs)
+            .build());
+    }
+
+    /// Returns the thread name, or the thread id if it has no name
+    pub fn thread(&self) -> String {
+        match &self.thread_name {
+            Some(name) => name.clone(),
+            None => format!("{:?}", self.thread_id),
+        }
+    }
+}
+
+impl From<&log::Record<'_>> for LogEvent {
+    /// Takes a snapshot of a record, timestamped now
+    fn from(record: &log::Record) -> LogEvent {
+        let thread = thread::current();
+        LogEvent {
+            timestamp: Local::now(),
+            instant: Instant::now(),
+            level: record.level(),
+            target: record.target().to_string(),
+            module_path: record.module_path().map(|m| m.to_string()),
+            file: record.file().map(|f| f.to_string()),
+            line: record.line(),
+            message: record.args().to_string(),
+            key_values: sys::key_values(record),
+            mdc: mdc::snapshot(),
+            scope: scope::current(),
+            thread_id: thread.id(),
+            thread_name: thread.name().map(|n| n.to_string()),
+        }
+    }
+}
+
+// The fields of an event as a formatter reads them, so that it can
+// format a LogEvent or a record borrowed in place
+pub(crate) trait EventFields {
+    fn timestamp(&self) -> DateTime<Local>;
+    fn instant(&self) -> Instant;
+    fn level(&self) -> Level;
+    fn target(&self) -> &str;
+    fn module_path(&self) -> Option<&str>;
+    fn file(&self) -> Option<&str>;
+    fn line(&self) -> Option<u32>;
+    fn message(&self) -> &str;
+    fn key_values(&self) -> &[(String, String)];
+    fn mdc(&self) -> &[(String, String)];
+    fn scope(&self) -> &[String];
+    fn thread(&self) -> Cow<'_, str>;
+}
+
+impl EventFields for LogEvent {
+    fn timestamp(&self) -> DateTime<Local> { self.timestamp }
+    fn instant(&self) -> Instant { self.instant }
+    fn level(&self) -> Level { self.level }
+    fn target(&self) -> &str { &self.target }
+    fn module_path(&self) -> Option<&str> { self.module_path.as_deref() }
+    fn file(&self) -> Option<&str> { self.file.as_deref() }
+    fn line(&self) -> Option<u32> { self.line }
+    fn message(&self) -> &str { &self.message }
+    fn key_values(&self) -> &[(String, String)] { &self.key_values }
+    fn mdc(&self) -> &[(String, String)] { &self.mdc }
+    fn scope(&self) -> &[String] { &self.scope }
+
+    fn thread(&self) -> Cow<'_, str> {
+        match &self.thread_name {
+            Some(name) => Cow::Borrowed(name),
+            None => Cow::Owned(format!("{:?}", self.thread_id)),
+        }
+    }
+}
+
+// A record being logged, which borrows what it can from the record
+// and captures the rest, such as the time and the mdc, on first use.
+// Formatting one only pays for the fields the format reads, and
+// reading a field again returns the same value.
+pub(crate) struct RecordEvent<'a> {
+    record: &'a log::Record<'a>,
+    timestamp: OnceCell<DateTime<Local>>,
+    instant: OnceCell<Instant>,
+    message: OnceCell<Cow<'a, str>>,
+    key_values: OnceCell<Vec<(String, String)>>,
+    mdc: OnceCell<Vec<(String, String)>>,
+    scope: OnceCell<Vec<String>>,
+}
+
+impl<'a> RecordEvent<'a> {
+    pub(crate) fn new(record: &'a log::Record<'a>) -> RecordEvent<'a> {
+        RecordEvent {
+            record,
+            timestamp: OnceCell::new(),
+            instant: OnceCell::new(),
+            message: OnceCell::new(),
+            key_values: OnceCell::new(),
+            mdc: OnceCell::new(),
+            scope: OnceCell::new(),
+        }
+    }
+}
+
+impl EventFields for RecordEvent<'_> {
+    fn timestamp(&self) -> DateTime<Local> {
+        *self.timestamp.get_or_init(Local::now)
+    }
+
+    fn instant(&self) -> Instant {
+        *self.instant.get_or_init(Instant::now)
+    }
+
+    fn level(&self) -> Level { self.record.level() }
+    fn target(&self) -> &str { self.record.target() }
+    fn module_path(&self) -> Option<&str> { self.record.module_path() }
+    fn file(&self) -> Option<&str> { self.record.file() }
+    fn line(&self) -> Option<u32> { self.record.line() }
+
+    // Messages without arguments are borrowed rather than copied
+    fn message(&self) -> &str {
+        self.message.get_or_init(|| match self.record.args().as_str() {
+            Some(message) => Cow::Borrowed(message),
+            None => Cow::Owned(self.record.args().to_string()),
+        })
+    }
+
+    fn key_values(&self) -> &[(String, String)] {
+        self.key_values.get_or_init(|| sys::key_values(self.record))
+    }
+
+    fn mdc(&self) -> &[(String, String)] {
+        self.mdc.get_or_init(mdc::snapshot)
+    }
+
+    fn scope(&self) -> &[String] {
+        self.scope.get_or_init(scope::current)
+    }
+
+    fn thread(&self) -> Cow<'_, str> {
+        let thread = thread::current();
+        match thread.name() {
+            Some(name) => Cow::Owned(name.to_string()),
+            None => Cow::Owned(format!("{:?}", thread.id())),
+        }
+    }
+}
@@ -1,16 +1,141 @@
 use strfmt::strfmt;
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use log::Level;
+use serde::Deserialize;
+use super::color::ColorPalette;
+use super::log_event::{LogEvent, RecordEvent, EventFields};
+use super::sys;
 
+/// The output format of a logger
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Lines formatted with the msg_format and timestamp_format
+    Text,
+    /// One JSON object per record with the fields timestamp,
+    /// level, target, file, line, message and scope (if any), plus any key-values
+    /// in a "kv" object and any mdc entries in an "mdc" object.
+    /// The timestamp uses the timestamp_format and is left out if
+    /// the timestamp_format is "".
+    Json,
+    /// One [GELF 1.1](https://go2docs.graylog.org/current/getting_in_log_data/gelf.html)
+    /// JSON object per record for Graylog, with the file, line,
+    /// target, mdc entries and key-values as additional fields
+    Gelf,
+}
+
+/// How the Text format writes control characters found in the
+/// message and in other fields which may hold user input: the
+/// target, module, file, mdc values, scope names and thread name
+///
+/// Without escaping, input containing a newline can forge log lines
+/// and ANSI escape sequences can corrupt a terminal. The JSON and
+/// GELF formats always escape control characters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Escape {
+    /// Writes fields as they are
+    None,
+    /// Writes newlines and carriage returns as \n and \r, and other
+    /// control characters, such as the ESC starting an ANSI sequence,
+    /// as \x1b or \u{85}. Backslashes are written as \\ so that
+    /// these cannot be forged. Tabs are left as they are.
+    Escape,
+    /// Removes ANSI escape sequences and control characters other than
+    /// tabs, replacing line breaks with spaces
+    Strip,
+    /// Keeps line breaks but indents each continuation line by four
+    /// spaces so it cannot pass for a record of its own. Other control
+    /// characters are escaped.
+    Indent,
+}
+
+/// Formats records and [LogEvents](struct.LogEvent.html) as text,
+/// JSON or GELF, as the loggers in this crate do
+///
+/// Each logger has a LogFormatter configured by its own methods,
+/// such as msg_format() and timestamp_format(). One can also be used
+/// directly, for example to format events taken from a queue.
+///
+/// # Example
+/// ```
+/// use log::{Level,Record};
+/// use poly_logger::{LogEvent,LogFormatter};
+///
+/// let event = LogEvent::from(&Record::builder()
+///     .args(format_args!("Deferred"))
+///     .level(Level::Warn)
+///     .target("app")
+///     .build());
+///
+/// let mut formatter = LogFormatter::new();
+/// formatter.msg_format("{level} {target} {args}");
+/// assert_eq!(formatter.event_msg(&event).unwrap(), "WARN app Deferred");
+/// ```
+///
+/// # Elapsed Example
+/// {elapsed} is measured from when the first logger was created, so
+/// loggers created at different times agree on it
+/// ```
+/// use log::Record;
+/// use poly_logger::{LogEvent,LogFormatter};
+///
+/// let mut early = LogFormatter::new();
+/// early.msg_format("{elapsed}");
+/// std::thread::sleep(std::time::Duration::from_millis(10));
+/// let late = early.clone();
+/// let mut rebuilt = LogFormatter::new();
+/// rebuilt.msg_format("{elapsed}");
+///
+/// let event = LogEvent::from(&Record::builder().args(format_args!("")).build());
+/// let elapsed = early.event_msg(&event).unwrap();
+/// assert_eq!(late.event_msg(&event).unwrap(), elapsed);
+/// assert_eq!(rebuilt.event_msg(&event).unwrap(), elapsed);
+/// ```
+///
 pub struct LogFormatter {
+    // Text or structured output
+    format: LogFormat,
+
+    // Host name for GELF output
+    host: String,
+
     // strftime format string
-    timestamp_format: &'static str,
+    timestamp_format: String,
 
     // e.g. [{timestamp}] {level} [{path}] - {msg}
-    msg_format: &'static str,
+    msg_format: String,
+
+    // The msg_format with each {level} placeholder wrapped in
+    // {color} tags, used to color the level without the escape
+    // codes counting towards its width
+    level_color_format: String,
 
     // Flag to indicate we need to do more expensive
     // formatting with strfmt
     use_strfmt: bool,
+
+    // The names of the placeholders in msg_format, so that
+    // only the fields it uses are rendered
+    placeholders: Vec<String>,
+
+    // Spaces to indent messages by per scope
+    indent: usize,
+
+    // Escaping of user controlled fields in Text output
+    escape: Escape,
+
+    // For {delta}, the nanos since the logging start
+    // of the last record
+    last: AtomicU64,
+
+    // ANSI color options
+    color: bool,
+    color_line: bool,
+    palette: ColorPalette,
 }
 
 // NOTE: Using default error type
@@ -19,99 +144,516 @@ type MsgResult = Result<String, strfmt::FmtError>;
 impl Clone for LogFormatter {
     fn clone(&self) -> LogFormatter {
         LogFormatter {
+            format: self.format,
+            host: self.host.clone(),
             timestamp_format: self.timestamp_format.clone(),
             msg_format: self.msg_format.clone(),
+            level_color_format: self.level_color_format.clone(),
             use_strfmt: self.use_strfmt,
+            placeholders: self.placeholders.clone(),
+            indent: self.indent,
+            escape: self.escape,
+            last: AtomicU64::new(self.last.load(Ordering::Relaxed)),
+            color: self.color,
+            color_line: self.color_line,
+            palette: self.palette.clone(),
         }
     }
 }
 
+impl Default for LogFormatter {
+    fn default() -> LogFormatter {
+        LogFormatter::new()
+    }
+}
+
 impl LogFormatter {
+    /// Instantiates a LogFormatter using the Text format,
+    /// the default message format and an ISO 8601 timestamp
     pub fn new() -> Self {
+        sys::logging_start();
         LogFormatter {
-            timestamp_format: "%+",
-            msg_format: "",
+            format: LogFormat::Text,
+            host: String::new(),
+            timestamp_format: "%+".to_string(),
+            msg_format: "".to_string(),
+            level_color_format: "".to_string(),
             use_strfmt: false,
+            placeholders: Vec::new(),
+            indent: 0,
+            escape: Escape::None,
+            last: AtomicU64::new(0),
+            color: false,
+            color_line: false,
+            palette: ColorPalette::default(),
         }
     }
 
-    // Set format options
-    pub fn timestamp_format(&mut self, format: &'static str) -> &mut Self {
-        self.timestamp_format = format;
+    /// Sets the output format
+    pub fn format(&mut self, format: LogFormat) -> &mut Self {
+        if format == LogFormat::Gelf && self.host.is_empty() {
+            self.host = sys::hostname();
+        }
+        self.format = format;
         self
     }
 
-    pub fn msg_format(&mut self, format: &'static str) -> &mut Self {
+    /// Sets the host name written in GELF output. The
+    /// default is the name of this host.
+    pub fn host(&mut self, host: &str) -> &mut Self {
+        self.host = host.to_string();
+        self
+    }
+
+    /// Sets the timestamp format, see
+    /// [GenLogger::timestamp_format](struct.GenLogger.html#method.timestamp_format)
+    pub fn timestamp_format(&mut self, format: &str) -> &mut Self {
+        self.timestamp_format = format.to_string();
+        self
+    }
+
+    /// Sets the message format, see
+    /// [GenLogger::msg_format](struct.GenLogger.html#method.msg_format)
+    pub fn msg_format(&mut self, format: &str) -> &mut Self {
         // Using custom format
         self.use_strfmt = true;
-        self.msg_format = format;
+        self.msg_format = format.to_string();
+        self.level_color_format = color_level_placeholders(format);
+        self.placeholders = placeholder_names(format);
+        self
+    }
+
+    /// Sets the spaces to indent messages by per scope
+    pub fn indent(&mut self, width: usize) -> &mut Self {
+        self.indent = width;
         self
     }
 
-    // Format value accessors
+    /// Sets how control characters are written in Text output
+    ///
+    /// # Example
+    /// ```
+    /// use log::Record;
+    /// use poly_logger::{Escape,LogEvent,LogFormatter};
+    ///
+    /// let mut formatter = LogFormatter::new();
+    /// formatter.msg_format("{target} {args}")
+    ///          .escape(Escape::Escape);
+    /// let event = LogEvent::from(&Record::builder()
+    ///     .args(format_args!("a\\nb\nc"))
+    ///     .target("app\x1b[31m")
+    ///     .build());
+    /// assert_eq!(formatter.event_msg(&event).unwrap(), r"app\x1b[31m a\\nb\nc");
+    /// ```
+    pub fn escape(&mut self, escape: Escape) -> &mut Self {
+        self.escape = escape;
+        self
+    }
+
+    /// Turns ANSI level colors on or off
+    pub fn color(&mut self, color: bool) -> &mut Self {
+        self.color = color;
+        self
+    }
+
+    /// Colors the whole line rather than just the level
+    pub fn color_line(&mut self, color_line: bool) -> &mut Self {
+        self.color_line = color_line;
+        self
+    }
+
+    /// Sets the colors used for each level
+    pub fn color_palette(&mut self, palette: ColorPalette) -> &mut Self {
+        self.palette = palette;
+        self
+    }
+
+    /// Renders the message format against a dummy record so
+    /// that invalid placeholders are caught up front. This does
+    /// not count as the previous record for {delta}.
+    ///
+    /// # Example
+    /// ```
+    /// use log::{Level,Record};
+    /// use poly_logger::{LogEvent,LogFormatter};
+    ///
+    /// let mut formatter = LogFormatter::new();
+    /// formatter.msg_format("{elapsed} {delta}");
+    /// assert!(formatter.validate().is_ok());
+    ///
+    /// // The first record's delta is from the start, like elapsed
+    /// std::thread::sleep(std::time::Duration::from_millis(10));
+    /// let event = LogEvent::from(&Record::builder().args(format_args!("")).build());
+    /// let msg = formatter.event_msg(&event).unwrap();
+    /// let (elapsed, delta) = msg.split_once(' ').unwrap();
+    /// assert_eq!(elapsed, delta);
+    ///
+    /// formatter.msg_format("{bogus}");
+    /// assert!(formatter.validate().is_err());
+    /// ```
+    pub fn validate(&self) -> Result<(), strfmt::FmtError> {
+        let items = chrono::format::StrftimeItems::new(&self.timestamp_format);
+        if items.into_iter().any(|i| i == chrono::format::Item::Error) {
+            let msg = format!("invalid timestamp format '{}'", self.timestamp_format);
+            return Err(strfmt::FmtError::Invalid(msg));
+        }
+
+        // Rendered by a clone so that {delta} is left as it is
+        let record = log::Record::builder()
+            .args(format_args!(""))
+            .build();
+        self.clone().msg(&record).map(|_| ())
+    }
+
+    /// Formats a record, timestamped now
     pub fn msg(&self, record: &log::Record) -> MsgResult {
+        self.fields_msg(&RecordEvent::new(record))
+    }
+
+    /// Formats a record with the default message format, as
+    /// loggers do when the message format is invalid
+    pub fn default_msg(&self, record: &log::Record) -> String {
+        self.default_fields_msg(&RecordEvent::new(record))
+    }
+
+    /// Formats an event, using its timestamp rather than the time now
+    pub fn event_msg(&self, event: &LogEvent) -> MsgResult {
+        self.fields_msg(event)
+    }
+
+    /// Formats an event with the default message format
+    pub fn default_event_msg(&self, event: &LogEvent) -> String {
+        self.default_fields_msg(event)
+    }
+
+    // Formats an event or a record being logged
+    pub(crate) fn fields_msg(&self, event: &dyn EventFields) -> MsgResult {
         // NOTE - Use strfmt only if custom message
         // as it's more expensive. 
         // Future option: We could add various
         // canned defaults for performance reasons
-        match self.use_strfmt {
-            false => {
-                Ok(self.default_msg(record))
+        match (self.format, self.use_strfmt) {
+            (LogFormat::Json, _) => {
+                Ok(self.json_msg(event))
             },
-            true => {
-                self.custom_msg(record)
+            (LogFormat::Gelf, _) => {
+                Ok(self.gelf_msg(event))
+            },
+            (LogFormat::Text, false) => {
+                Ok(self.default_fields_msg(event))
+            },
+            (LogFormat::Text, true) => {
+                self.custom_msg(event)
             },
         }
     }
 
-    pub fn default_msg(&self, record: &log::Record) -> String {
-        format!(
+    fn json_msg(&self, event: &dyn EventFields) -> String {
+        let mut obj = serde_json::Map::new();
+        if !self.timestamp_format.is_empty() {
+            obj.insert("timestamp".into(), self.timestamp(event).into());
+        }
+        obj.insert("level".into(), event.level().as_str().into());
+        obj.insert("target".into(), event.target().into());
+        obj.insert("file".into(), self.file(event).into());
+        obj.insert("line".into(), self.line(event).into());
+        obj.insert("message".into(), event.message().into());
+        if !event.scope().is_empty() {
+            obj.insert("scope".into(), event.scope().join(">").into());
+        }
+
+        if !event.key_values().is_empty() {
+            let kv: serde_json::Map<_, _> = event.key_values().iter()
+                .map(|(k, v)| (k.clone(), v.clone().into()))
+                .collect();
+            obj.insert("kv".into(), kv.into());
+        }
+        if !event.mdc().is_empty() {
+            let mdc: serde_json::Map<_, _> = event.mdc().iter()
+                .map(|(k, v)| (k.clone(), v.clone().into()))
+                .collect();
+            obj.insert("mdc".into(), mdc.into());
+        }
+        serde_json::Value::Object(obj).to_string()
+    }
+
+    pub(crate) fn default_fields_msg(&self, event: &dyn EventFields) -> String {
+        let msg = format!(
             "[{timestamp}] {level} [{file}:{line}] {args}", 
-            timestamp=self.timestamp(),
-            level=record.metadata().level(), 
-            file=self.file(record),
-            line=self.line(record),
-            args=record.args())
+            timestamp=self.timestamp(event),
+            level=self.level(event.level()),
+            file=self.escape.apply(self.file(event)),
+            line=self.line(event),
+            args=self.args(event));
+        self.color_whole_line(event.level(), msg)
     }
 
-    fn custom_msg(&self, record: &log::Record) -> MsgResult {
+    // Renders only the placeholders the format uses, leaving
+    // unknown ones for strfmt to report
+    fn custom_msg(&self, event: &dyn EventFields) -> MsgResult {
+        let level = event.level();
+        let mut times = None;
         let mut vars = HashMap::new();
-        vars.insert("timestamp".to_string(), self.timestamp());
-        vars.insert("level".to_string(), 
-                    record.metadata().level().to_string());
-        vars.insert("file".to_string(), self.file(record));
-        vars.insert("line".to_string(), self.line(record).to_string());
-        vars.insert("args".to_string(), record.args().to_string());
-        strfmt(self.msg_format, &vars)
-    }
-
-    fn timestamp(&self) -> String {
-        match &self.timestamp_format {
-            &"" => {
+        for name in &self.placeholders {
+            let value = match name.as_str() {
+                "timestamp" => self.timestamp(event),
+                "level" => level.to_string(),
+                "file" => self.escape.apply(self.file(event)).into_owned(),
+                "line" => self.line(event).to_string(),
+                "args" => self.args(event),
+                "target" => self.escape.apply(event.target()).into_owned(),
+                "module" => self.escape.apply(event.module_path().unwrap_or_default()).into_owned(),
+                "thread" => self.escape.apply(&event.thread()).into_owned(),
+                "scope" => self.escape.apply(&event.scope().join(">")).into_owned(),
+                "elapsed" => {
+                    let (elapsed, _) = *times.get_or_insert_with(|| self.elapsed(event));
+                    format!("{:.6}", elapsed.as_secs_f64())
+                },
+                "delta" => {
+                    let (_, delta) = *times.get_or_insert_with(|| self.elapsed(event));
+                    format!("{:.6}", delta.as_secs_f64())
+                },
+                "uptime_ms" => {
+                    let uptime = event.instant().saturating_duration_since(sys::process_start());
+                    uptime.as_millis().to_string()
+                },
+                // {mdc} is every entry, and {mdc.key} is empty if not set
+                "mdc" => {
+                    let mdc: Vec<String> = event.mdc().iter()
+                        .map(|(k, v)| format!("{}={}", k, self.escape.apply(v)))
+                        .collect();
+                    mdc.join(" ")
+                },
+                name => match name.strip_prefix("mdc.") {
+                    Some(key) => event.mdc().iter()
+                        .find(|(k, _)| k == key)
+                        .map(|(_, v)| self.escape.apply(v).into_owned())
+                        .unwrap_or_default(),
+                    None => continue,
+                },
+            };
+            vars.insert(name.clone(), value);
+        }
+
+        // {color}...{/color} tags are empty unless color is on
+        let (start, reset) = match self.color {
+            true => (self.palette.start(level), self.palette.reset().to_string()),
+            false => (String::new(), String::new()),
+        };
+        vars.insert("color".to_string(), start);
+        vars.insert("/color".to_string(), reset);
+
+        let format = match self.color && !self.color_line {
+            true => &self.level_color_format,
+            false => &self.msg_format,
+        };
+        let msg = strfmt(format, &vars)?;
+        Ok(self.color_whole_line(level, msg))
+    }
+
+    fn gelf_msg(&self, event: &dyn EventFields) -> String {
+        let message = event.message();
+        let short_message = message.lines().next().unwrap_or("");
+        let timestamp = event.timestamp().timestamp_micros() as f64 / 1_000_000.0;
+
+        let mut obj = serde_json::Map::new();
+        obj.insert("version".into(), "1.1".into());
+        obj.insert("host".into(), self.host.clone().into());
+        if short_message.len() != message.len() {
+            obj.insert("short_message".into(), short_message.into());
+            obj.insert("full_message".into(), message.into());
+        } else {
+            obj.insert("short_message".into(), message.into());
+        }
+        obj.insert("timestamp".into(), timestamp.into());
+        obj.insert("level".into(), sys::syslog_severity(event.level()).into());
+        obj.insert("_file".into(), self.file(event).into());
+        obj.insert("_line".into(), self.line(event).into());
+        obj.insert("_target".into(), event.target().into());
+        if !event.scope().is_empty() {
+            obj.insert("_scope".into(), event.scope().join(">").into());
+        }
+
+        // Additional field names may only contain word chars,
+        // dots and dashes, and _id is reserved
+        for (key, value) in event.mdc().iter().chain(event.key_values()) {
+            let mut name: String = key.chars()
+                .map(|c| match c.is_ascii_alphanumeric() || "_.-".contains(c) {
+                    true => c,
+                    false => '_',
+                })
+                .collect();
+            if name == "id" {
+                name = "kv_id".to_string();
+            }
+            obj.insert(format!("_{}", name), value.clone().into());
+        }
+        serde_json::Value::Object(obj).to_string()
+    }
+
+    // The level name, colored unless the whole line is colored
+    fn level(&self, level: Level) -> String {
+        match self.color && !self.color_line {
+            true => format!("{}{}{}", self.palette.start(level), level,
+                            self.palette.reset()),
+            false => level.to_string(),
+        }
+    }
+
+    fn color_whole_line(&self, level: Level, msg: String) -> String {
+        match self.color && self.color_line {
+            true => format!("{}{}{}", self.palette.start(level), msg,
+                            self.palette.reset()),
+            false => msg,
+        }
+    }
+
+    fn timestamp(&self, event: &dyn EventFields) -> String {
+        match self.timestamp_format.as_str() {
+            "" => {
                 "".to_string()
             },
             f => {
-                // Note that we might want to separate the 
-                // timestamping of a message with the formatting of the
-                // timestamp, especially if we move to a producer/consumer
-                // queue
-                let now = chrono::Local::now();
-                now.format(&f).to_string()
+                event.timestamp().format(f).to_string()
             }
         }
     }
 
-    fn line(&self, record: &log::Record) -> u32 {
-        match record.line() {
-            Some(l) => l,
-            None => 0,
+    // The time from the first logger's creation to the event, and
+    // from the previous event to this one. Records logged concurrently
+    // may arrive out of order, so delta is never negative.
+    fn elapsed(&self, event: &dyn EventFields) -> (Duration, Duration) {
+        let elapsed = event.instant().saturating_duration_since(sys::logging_start());
+        let nanos = elapsed.as_nanos() as u64;
+        let last = self.last.fetch_max(nanos, Ordering::Relaxed);
+        (elapsed, Duration::from_nanos(nanos.saturating_sub(last)))
+    }
+
+    // The message, escaped and indented by the scope depth
+    fn args(&self, event: &dyn EventFields) -> String {
+        let message = self.escape.apply(event.message());
+        match self.indent * event.scope().len() {
+            0 => message.into_owned(),
+            n => format!("{:n$}{}", "", message, n = n),
+        }
+    }
+
+    fn line(&self, event: &dyn EventFields) -> u32 {
+        event.line().unwrap_or_default()
+    }
+
+    fn file<'a>(&self, event: &'a dyn EventFields) -> &'a str {
+        event.file().unwrap_or("<no_file>")
+    }
+}
+
+// The names of the placeholders in a format, without any
+// format spec, skipping braces escaped as {{
+fn placeholder_names(format: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut rest = format;
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start + 1..];
+        if let Some(after) = rest.strip_prefix('{') {
+            rest = after;
+            continue;
+        }
+        let end = rest.find(['}', ':']).unwrap_or(rest.len());
+        let name = &rest[..end];
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+        rest = &rest[end..];
+    }
+    names
+}
+
+// Wraps each {level} placeholder, including any format
+// spec such as {level:<5}, in {color} and {/color} tags
+fn color_level_placeholders(format: &str) -> String {
+    let mut out = String::with_capacity(format.len() + 32);
+    let mut rest = format;
+    while let Some(start) = rest.find("{level") {
+        let after = &rest[start + "{level".len()..];
+        let escaped = rest[..start].ends_with('{');
+        let end = match after.chars().next() {
+            Some('}') | Some(':') if !escaped => after.find('}'),
+            _ => None,
+        };
+        match end {
+            Some(end) => {
+                let placeholder = &rest[start..start + "{level".len() + end + 1];
+                out.push_str(&rest[..start]);
+                out.push_str("{color}");
+                out.push_str(placeholder);
+                out.push_str("{/color}");
+                rest = &rest[start + placeholder.len()..];
+            },
+            None => {
+                out.push_str(&rest[..start + 1]);
+                rest = &rest[start + 1..];
+            },
         }
     }
+    out.push_str(rest);
+    out
+}
 
-    fn file(&self, record: &log::Record) -> String {
-        match record.file() {
-            Some(f) => f.to_string(),
-            None => "<no_file>".to_string(),
+impl Escape {
+    // Returns the text with control characters
+    // handled according to this policy
+    pub(crate) fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let escapes = |c| is_unsafe(c) || (*self == Escape::Escape && c == '\\');
+        if *self == Escape::None || !text.chars().any(escapes) {
+            return Cow::Borrowed(text);
+        }
+
+        let mut out = String::with_capacity(text.len() + 8);
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (self, c) {
+                (_, '\t') => out.push(c),
+                (Escape::Escape, '\\') => out.push_str("\\\\"),
+                (Escape::Strip, '\u{1b}') => skip_ansi(&mut chars),
+                (Escape::Strip, '\n' | '\r' | '\u{2028}' | '\u{2029}') => out.push(' '),
+                (Escape::Strip, c) if is_unsafe(c) => {},
+                (Escape::Indent, '\r') if chars.peek() == Some(&'\n') => {},
+                (Escape::Indent, '\n') => out.push_str("\n    "),
+                (_, '\n') => out.push_str("\\n"),
+                (_, '\r') => out.push_str("\\r"),
+                (_, c) if is_unsafe(c) && (c as u32) < 0x80 => {
+                    out.push_str(&format!("\\x{:02x}", c as u32));
+                },
+                (_, c) if is_unsafe(c) => out.push_str(&c.escape_unicode().to_string()),
+                (_, c) => out.push(c),
+            }
         }
+        Cow::Owned(out)
+    }
+}
+
+// Control characters, other than tab, and the
+// Unicode line and paragraph separators
+fn is_unsafe(c: char) -> bool {
+    (c.is_control() && c != '\t') || c == '\u{2028}' || c == '\u{2029}'
+}
+
+// Skips the rest of an ANSI escape sequence after its ESC
+fn skip_ansi(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    match chars.next() {
+        // CSI: parameter and intermediate bytes then a final byte
+        Some('[') => {
+            while chars.next_if(|c| (' '..='?').contains(c)).is_some() {}
+            chars.next_if(|c| ('@'..='~').contains(c));
+        },
+        // OSC: up to BEL or ESC \\
+        Some(']') => {
+            while let Some(c) = chars.next() {
+                if c == '\u{7}' || (c == '\u{1b}' && chars.next_if_eq(&'\\').is_some()) {
+                    break;
+                }
+            }
+        },
+        _ => {},
     }
 }
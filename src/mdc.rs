@@ -0,0 +1,153 @@
+//! A thread-local mapped diagnostic context (MDC)
+//!
+//! Key-values put in the context appear in every record logged by the
+//! same thread, so a request ID can be set once per request rather than
+//! passed to every log call. Loggers show them with the {mdc} placeholder,
+//! which renders all of them as "key=value" pairs, or with {mdc.key} for
+//! a single one, and include them in an "mdc" object (or as additional
+//! fields for GELF) in the structured formats.
+//!
+//! Entries form a stack, so an inner push() of a key shadows an outer
+//! one until popped. scoped() pushes an entry which is popped when the
+//! returned guard is dropped.
+//!
+//! # Example
+//! ```
+//! use log::{info,Level};
+//! use poly_logger::{mdc,CaptureLogger,LogEvent};
+//!
+//! fn handle_request(id: u32) {
+//!     let _guard = mdc::scoped("request_id", &id.to_string());
+//!     info!("Handling request");
+//! }
+//!
+//! let capture = CaptureLogger::capture();
+//! handle_request(42);
+//! assert_eq!(capture.records()[0].mdc, vec![("request_id".to_string(), "42".to_string())]);
+//! assert!(mdc::get("request_id").is_none());
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+
+thread_local! {
+    static CONTEXT: RefCell<Vec<Entry>> = const { RefCell::new(Vec::new()) };
+    static NEXT_ID: Cell<u64> = const { Cell::new(0) };
+}
+
+// An entry in the context, with an id so that a guard
+// finds its own entry even after others have been removed
+struct Entry {
+    id: u64,
+    key: String,
+    value: String,
+}
+
+/// Pops the entry pushed by [scoped](fn.scoped.html) when dropped
+#[must_use = "the entry is removed as soon as the guard is dropped"]
+pub struct MdcGuard {
+    id: u64,
+    // The guard must be dropped on the thread which created it
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Pushes a key-value onto this thread's context
+pub fn push(key: &str, value: &str) {
+    push_entry(key, value);
+}
+
+/// Pops the most recently pushed key-value from this thread's context
+pub fn pop() -> Option<(String, String)> {
+    CONTEXT.with(|c| c.borrow_mut().pop().map(|e| (e.key, e.value)))
+}
+
+/// Pushes a key-value which is popped when the returned guard is
+/// dropped, along with anything pushed after it
+///
+/// If the entry has already been removed, by pop(), remove() or
+/// clear(), dropping the guard leaves the context as it is.
+///
+/// # Example
+/// ```
+/// use poly_logger::mdc;
+///
+/// // Dropping an inner guard after an outer key was removed
+/// let outer = mdc::scoped("a", "1");
+/// let inner = mdc::scoped("b", "2");
+/// mdc::remove("a");
+/// drop(inner);
+/// assert!(mdc::snapshot().is_empty());
+/// drop(outer);
+///
+/// // Dropping a guard whose entry was popped and replaced
+/// let guard = mdc::scoped("a", "1");
+/// mdc::pop();
+/// mdc::push("y", "2");
+/// drop(guard);
+/// assert_eq!(mdc::get("y").as_deref(), Some("2"));
+/// ```
+pub fn scoped(key: &str, value: &str) -> MdcGuard {
+    let id = push_entry(key, value);
+    MdcGuard { id, _not_send: PhantomData }
+}
+
+/// Returns the current value of a key
+pub fn get(key: &str) -> Option<String> {
+    CONTEXT.with(|c| {
+        c.borrow().iter().rev()
+            .find(|e| e.key == key)
+            .map(|e| e.value.clone())
+    })
+}
+
+/// Removes every entry for a key
+pub fn remove(key: &str) {
+    CONTEXT.with(|c| c.borrow_mut().retain(|e| e.key != key));
+}
+
+/// Removes everything from this thread's context
+pub fn clear() {
+    CONTEXT.with(|c| c.borrow_mut().clear());
+}
+
+/// Returns the current key-values, in the order the keys were
+/// first pushed, with shadowed values left out
+pub fn snapshot() -> Vec<(String, String)> {
+    CONTEXT.try_with(|c| {
+        let context = c.borrow();
+        let mut current: Vec<(String, String)> = Vec::with_capacity(context.len());
+        for entry in context.iter() {
+            match current.iter_mut().find(|(k, _)| *k == entry.key) {
+                Some(current) => current.1 = entry.value.clone(),
+                None => current.push((entry.key.clone(), entry.value.clone())),
+            }
+        }
+        current
+    }).unwrap_or_default()
+}
+
+// Pushes an entry, returning its id
+fn push_entry(key: &str, value: &str) -> u64 {
+    let id = NEXT_ID.with(|n| {
+        let id = n.get();
+        n.set(id + 1);
+        id
+    });
+    CONTEXT.with(|c| c.borrow_mut().push(Entry {
+        id,
+        key: key.to_string(),
+        value: value.to_string(),
+    }));
+    id
+}
+
+impl Drop for MdcGuard {
+    fn drop(&mut self) {
+        let _ = CONTEXT.try_with(|c| {
+            let mut context = c.borrow_mut();
+            if let Some(pos) = context.iter().position(|e| e.id == self.id) {
+                context.truncate(pos);
+            }
+        });
+    }
+}
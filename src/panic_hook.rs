@@ -0,0 +1,164 @@
+use std::backtrace::Backtrace;
+use std::cell::Cell;
+use std::panic::{self, PanicHookInfo};
+use std::thread;
+use super::sys::{self, FlagGuard};
+
+thread_local! {
+    static IN_PANIC_HOOK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Logs panics through the installed logger
+///
+/// Once installed, a panic is logged at Error level with its message,
+/// location and thread, and optionally a backtrace, and then every
+/// logger is flushed so the record reaches files and remote sinks
+/// before the process exits. The panic hook which was installed
+/// before, which by default prints to stderr, is then called unless
+/// chain(false) was set.
+///
+/// A panic inside one of this crate's loggers, or while the hook is
+/// logging, is not logged, as the logger may be holding its locks.
+/// It is still passed to the previous hook.
+///
+/// # Example
+/// ```
+/// use log::{Level,LevelFilter};
+/// use poly_logger::{CaptureLogger,PanicHook};
+///
+/// let logger = CaptureLogger::new(LevelFilter::Info);
+/// let capture = logger.handle();
+/// logger.init().unwrap();
+///
+/// let mut hook = PanicHook::new();
+/// hook.chain(false);
+/// hook.install();
+///
+/// let worker = std::thread::Builder::new().name("worker".into())
+///     .spawn(|| panic!("Out of widgets"))
+///     .unwrap();
+/// assert!(worker.join().is_err());
+///
+/// let record = &capture.records()[0];
+/// assert_eq!(record.level, Level::Error);
+/// assert_eq!(record.target, "panic");
+/// assert!(record.message.starts_with("thread 'worker' panicked at "));
+/// assert!(record.message.ends_with(": Out of widgets"));
+/// ```
+///
+/// # Panicking Logger Example
+/// ```
+/// use std::io::{self,Write};
+/// use std::sync::atomic::{AtomicUsize,Ordering};
+/// use log::{info,LevelFilter};
+/// use poly_logger::{GenLogger,PanicHook};
+///
+/// // A writer which panics while the logger holds its lock
+/// struct Faulty;
+/// impl Write for Faulty {
+///     fn write(&mut self, _: &[u8]) -> io::Result<usize> { panic!("Disk on fire") }
+///     fn flush(&mut self) -> io::Result<()> { Ok(()) }
+/// }
+/// GenLogger::new(LevelFilter::Info, Faulty).init().unwrap();
+///
+/// static CHAINED: AtomicUsize = AtomicUsize::new(0);
+/// std::panic::set_hook(Box::new(|_| { CHAINED.fetch_add(1, Ordering::Relaxed); }));
+/// PanicHook::new().install();
+///
+/// // Neither deadlocks nor recurses, and the previous hook still runs
+/// let worker = std::thread::spawn(|| info!("Hello"));
+/// assert!(worker.join().is_err());
+/// assert_eq!(CHAINED.load(Ordering::Relaxed), 1);
+/// ```
+///
+pub struct PanicHook {
+    backtrace: bool,
+    target: String,
+    chain: bool,
+}
+
+impl PanicHook {
+    /// Instantiates a PanicHook with the default options
+    pub fn new() -> PanicHook {
+        PanicHook {
+            backtrace: false,
+            target: "panic".to_string(),
+            chain: true,
+        }
+    }
+
+    /// Installs the hook. This moves self so is the last
+    /// method to call on this object.
+    pub fn install(self) {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !sys::in_logger() {
+                if let Some(_guard) = FlagGuard::set(&IN_PANIC_HOOK) {
+                    self.log(info);
+                }
+            }
+            if self.chain {
+                previous(info);
+            }
+        }));
+    }
+
+    /// Includes a backtrace in the record. The default is false.
+    pub fn backtrace(&mut self, backtrace: bool) -> &mut Self {
+        self.backtrace = backtrace;
+        self
+    }
+
+    /// Sets the target of the record. The default is "panic".
+    pub fn target(&mut self, target: &str) -> &mut Self {
+        self.target = target.to_string();
+        self
+    }
+
+    /// Calls the previously installed panic hook after logging.
+    /// The default is true.
+    pub fn chain(&mut self, chain: bool) -> &mut Self {
+        self.chain = chain;
+        self
+    }
+
+    fn log(&self, info: &PanicHookInfo) {
+        let payload = info.payload();
+        let msg = match payload.downcast_ref::<&str>() {
+            Some(s) => *s,
+            None => match payload.downcast_ref::<String>() {
+                Some(s) => s.as_str(),
+                None => "Box<dyn Any>",
+            },
+        };
+        let thread = thread::current();
+        let name = thread.name().unwrap_or("<unnamed>");
+
+        let mut text = format!("thread '{}' panicked at ", name);
+        if let Some(location) = info.location() {
+            text.push_str(&format!("{}:{}:{}", location.file(), location.line(),
+                                   location.column()));
+        }
+        text.push_str(&format!(": {}", msg));
+        if self.backtrace {
+            text.push_str(&format!("\nstack backtrace:\n{}", Backtrace::force_capture()));
+        }
+
+        let kvs = [("thread", name)];
+        log::logger().log(&log::Record::builder()
+            .args(format_args!("{}", text))
+            .level(log::Level::Error)
+            .target(&self.target)
+            .file(info.location().map(|l| l.file()))
+            .line(info.location().map(|l| l.line()))
+            .key_values(&kvs)
+            .build());
+        log::logger().flush();
+    }
+}
+
+impl Default for PanicHook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
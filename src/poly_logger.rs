@@ -1,16 +1,21 @@
 use log::{Level, SetLoggerError, MetadataBuilder};
 use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use super::flush_guard::FlushGuard;
+use super::stats::{CountedLog, LoggerStats, StatsSnapshot};
+use super::sys;
 
 /// Implements a super-logger that can redirect to other loggers
 ///
-/// This is useful if you want to log some messages to STDERR or 
-/// STDOUT and some to File in perhaps a different format. When 
+/// This is useful if you want to log some messages to STDERR or
+/// STDOUT and some to File in perhaps a different format. When
 /// using PolyLogger, you create any number of child loggers and
 /// add them to the PolyLogger. Then you set the PolyLogger instance
 /// as the only one to be passed to the log system via init().
 ///
 /// # Example
-/// Create a file logger with the default timestamp/message format, 
+/// Create a file logger with the default timestamp/message format,
 /// and also a raw logger for debug messages which gets sent to
 /// STDERR.
 /// ```
@@ -32,76 +37,471 @@ use std::fmt;
 /// debug!("This only goes to stderr");
 /// ```
 ///
+/// # Named Children
+/// Children may be registered under a name with add_named(). A
+/// [PolyHandle](struct.PolyHandle.html) obtained before init() can
+/// then be used to add, remove, replace, enable or disable named
+/// children while logging continues.
+/// ```
+/// use log::{debug,info,LevelFilter};
+/// use poly_logger::{PolyLogger,StderrLogger};
+///
+/// let mut poly_log = PolyLogger::new();
+/// poly_log.add_named("console", StderrLogger::new(LevelFilter::Info));
+/// let handle = poly_log.handle();
+/// poly_log.init().unwrap();
+///
+/// info!("Only the console logger is attached");
+///
+/// // Temporarily attach a debug logger
+/// let mut debug_log = StderrLogger::new(LevelFilter::Debug);
+/// debug_log.msg_format("DEBUG LOG: {args}");
+/// handle.add("debug", debug_log);
+/// debug!("This goes to the debug logger");
+///
+/// handle.disable("console");
+/// info!("This only goes to the debug logger");
+///
+/// handle.remove("debug");
+/// handle.enable("console");
+/// debug!("This goes nowhere");
+/// ```
+///
 pub struct PolyLogger {
-    loggers: Vec<Box<dyn log::Log>>,
+    shared: Arc<Shared>,
+}
+
+/// A handle to the children of a [PolyLogger](struct.PolyLogger.html)
+/// which remains usable after the PolyLogger has been moved into
+/// the log system by init().
+///
+/// If the PolyLogger is the installed logger, changes that raise or
+/// lower the maximum level of the enabled children also update
+/// [log::set_max_level](https://docs.rs/log/0.4.11/log/fn.set_max_level.html).
+/// Note that a PolyLogger nested in another PolyLogger does not
+/// propagate its level changes up to its parent.
+///
+/// Children may use a handle from inside their log() method, e.g.
+/// to remove themselves. Records being logged while the children
+/// change go to the children as they were when logging started.
+///
+/// # Example
+/// ```
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::{CallbackLogger,PolyLogger};
+///
+/// let mut poly_log = PolyLogger::new();
+/// let handle = poly_log.handle();
+///
+/// // A child which removes itself after its first record
+/// let once = handle.clone();
+/// poly_log.add_named("once", CallbackLogger::new(LevelFilter::Info, move |_| {
+///     once.remove("once");
+/// }));
+///
+/// poly_log.log(&Record::builder()
+///     .args(format_args!("Removes the child"))
+///     .level(Level::Info)
+///     .build());
+/// assert!(handle.names().is_empty());
+/// ```
+#[derive(Clone)]
+pub struct PolyHandle {
+    shared: Arc<Shared>,
+}
+
+// State shared between a PolyLogger and its handles. The children
+// are copied on write so that log() can take a snapshot and release
+// the lock before calling them.
+struct Shared {
+    children: RwLock<Arc<Vec<Child>>>,
+    max_level: AtomicUsize,
+    installed: AtomicBool,
+}
+
+#[derive(Clone)]
+struct Child {
+    name: String,
+    logger: Arc<dyn log::Log>,
     max_level: Level,
+    enabled: bool,
+    // Records held back from the child, plus the counts of
+    // any loggers it replaced
+    stats: Arc<LoggerStats>,
+    // The child's own stats, if it is a CountedLog
+    source: Option<Arc<LoggerStats>>,
 }
 
 impl PolyLogger {
     /// Instantiate a new PolyLogger
     pub fn new() -> Self {
-        PolyLogger{loggers: Vec::new(), max_level: Level::Error}
+        let shared = Shared {
+            children: RwLock::new(Arc::new(Vec::new())),
+            max_level: AtomicUsize::new(Level::Error as usize),
+            installed: AtomicBool::new(false),
+        };
+        PolyLogger{shared: Arc::new(shared)}
     }
-    
+
     /// Initializes the log interface using this PolyLogger
     /// as a boxed logger. This moves self so is the last
     /// method to call on this object.
     pub fn init(self) -> Result<(), SetLoggerError> {
-        log::set_max_level(self.max_level.to_level_filter());
-        log::set_boxed_logger(Box::new(self))
+        log::set_max_level(self.max_level().to_level_filter());
+        let shared = self.shared.clone();
+        log::set_boxed_logger(Box::new(self))?;
+        shared.installed.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Initializes the log interface like init(), returning a
+    /// [FlushGuard](struct.FlushGuard.html) which flushes every
+    /// child when dropped
+    pub fn init_with_guard(self) -> Result<FlushGuard, SetLoggerError> {
+        self.init()?;
+        Ok(FlushGuard::new())
     }
 
-    /// Returns the maximum log Level of any of the child
+    /// Returns the maximum log Level of any of the enabled child
     /// loggers added to this PolyLogger
     pub fn max_level(&self) -> Level {
-        self.max_level
+        self.shared.max_level()
+    }
+
+    /// Returns a handle which can be used to modify the children
+    /// of this PolyLogger after it has been moved by init()
+    pub fn handle(&self) -> PolyHandle {
+        PolyHandle{shared: self.shared.clone()}
     }
 
     /// Adds a logger to this PolyLogger. This can be anything that
     /// implements the [log::Log](https://docs.rs/log/0.4.11/log/) interface.
+    /// The child is given a generated name of the form "logger{n}".
     pub fn add<T: log::Log + 'static>(&mut self, logger: T) {
+        let mut lock = self.shared.children.write().unwrap();
+        let children = Arc::make_mut(&mut lock);
+        let mut n = children.len();
+        let name = loop {
+            let name = format!("logger{}", n);
+            if !children.iter().any(|c| c.name == name) {
+                break name;
+            }
+            n += 1;
+        };
+        children.push(Child::new(&name, Arc::new(logger), None));
+        self.shared.update_max_level(children);
+    }
+
+    /// Adds a logger to this PolyLogger under the given name.
+    /// Returns false (and drops the logger) if a child with
+    /// this name already exists.
+    pub fn add_named<T: log::Log + 'static>(&mut self, name: &str, logger: T) -> bool {
+        self.handle().add(name, logger)
+    }
+
+    /// Adds a logger which keeps its own stats under the given name,
+    /// so that its [PolyHandle::stats](struct.PolyHandle.html#method.stats)
+    /// are what it wrote, lost or failed to write. Returns false (and
+    /// drops the logger) if a child with this name already exists.
+    pub fn add_counted<T: CountedLog + 'static>(&mut self, name: &str, logger: T) -> bool {
+        self.handle().add_counted(name, logger)
+    }
+}
+
+impl Default for PolyLogger {
+    fn default() -> Self {
+        PolyLogger::new()
+    }
+}
+
+impl PolyHandle {
+    /// Adds a named child logger. Returns false (and drops the
+    /// logger) if a child with this name already exists.
+    pub fn add<T: log::Log + 'static>(&self, name: &str, logger: T) -> bool {
+        self.insert(name, Arc::new(logger), None)
+    }
+
+    /// Adds a named child logger which keeps its own stats.
+    /// See [PolyLogger::add_counted](struct.PolyLogger.html#method.add_counted)
+    pub fn add_counted<T: CountedLog + 'static>(&self, name: &str, logger: T) -> bool {
+        let stats = logger.stats();
+        self.insert(name, Arc::new(logger), Some(stats))
+    }
+
+    pub(crate) fn insert(&self, name: &str, logger: Arc<dyn log::Log>,
+                         source: Option<Arc<LoggerStats>>) -> bool {
+        let mut lock = self.shared.children.write().unwrap();
+        if lock.iter().any(|c| c.name == name) {
+            return false;
+        }
+        let children = Arc::make_mut(&mut lock);
+        children.push(Child::new(name, logger, source));
+        self.shared.update_max_level(children);
+        true
+    }
+
+    /// Removes the named child logger, flushing it first.
+    /// Returns the removed logger if it was found.
+    pub fn remove(&self, name: &str) -> Option<Arc<dyn log::Log>> {
+        let mut lock = self.shared.children.write().unwrap();
+        let index = lock.iter().position(|c| c.name == name)?;
+        let children = Arc::make_mut(&mut lock);
+        let child = children.remove(index);
+        self.shared.update_max_level(children);
+        drop(lock);
+
+        child.logger.flush();
+        Some(child.logger)
+    }
+
+    /// Replaces the named child logger, or adds it if there is no
+    /// child with this name. The previous logger is flushed and
+    /// returned. A replaced child keeps its enabled/disabled state
+    /// and its stats.
+    pub fn replace<T: log::Log + 'static>(&self, name: &str, logger: T) -> Option<Arc<dyn log::Log>> {
+        self.swap(name, Arc::new(logger), None)
+    }
+
+    /// Replaces or adds a named child logger which keeps its own
+    /// stats, like replace()
+    pub fn replace_counted<T: CountedLog + 'static>(&self, name: &str, logger: T) -> Option<Arc<dyn log::Log>> {
+        let stats = logger.stats();
+        self.swap(name, Arc::new(logger), Some(stats))
+    }
+
+    pub(crate) fn swap(&self, name: &str, logger: Arc<dyn log::Log>,
+                       source: Option<Arc<LoggerStats>>) -> Option<Arc<dyn log::Log>> {
+        let mut lock = self.shared.children.write().unwrap();
+        let children = Arc::make_mut(&mut lock);
+        let mut new_child = Child::new(name, logger, source);
+        let old = match children.iter_mut().find(|c| c.name == name) {
+            Some(child) => {
+                new_child.enabled = child.enabled;
+                new_child.stats = child.stats.clone();
+                if let Some(source) = &child.source {
+                    child.stats.absorb(&source.snapshot());
+                }
+                Some(std::mem::replace(child, new_child))
+            },
+            None => {
+                children.push(new_child);
+                None
+            },
+        };
+        self.shared.update_max_level(children);
+        drop(lock);
+
+        old.map(|child| {
+            child.logger.flush();
+            child.logger
+        })
+    }
+
+    /// Enables the named child logger. Returns false if not found.
+    pub fn enable(&self, name: &str) -> bool {
+        self.set_enabled(name, true)
+    }
+
+    /// Disables the named child logger so that it no longer receives
+    /// records. Returns false if not found.
+    pub fn disable(&self, name: &str) -> bool {
+        self.set_enabled(name, false)
+    }
+
+    /// Returns true if the named child exists and is enabled
+    pub fn is_enabled(&self, name: &str) -> bool {
+        let children = self.shared.children.read().unwrap();
+        children.iter().any(|c| c.name == name && c.enabled)
+    }
+
+    /// Returns the names of all children in the order they were added
+    pub fn names(&self) -> Vec<String> {
+        let children = self.shared.children.read().unwrap();
+        children.iter().map(|c| c.name.clone()).collect()
+    }
+
+    /// Returns the maximum log Level of any of the enabled children
+    pub fn max_level(&self) -> Level {
+        self.shared.max_level()
+    }
+
+    /// Returns the stats of the named child. For a child added with
+    /// add_counted() these are the stats the logger keeps itself,
+    /// while other children count the records passed to them as
+    /// written, without their bytes, and the records held back from
+    /// them, because of their level or the child being disabled, as
+    /// filtered. A replaced child keeps its counts.
+    ///
+    /// # Example
+    /// ```
+    /// use log::{Log,Level,LevelFilter,Record};
+    /// use poly_logger::{PolyLogger,StderrLogger};
+    ///
+    /// let mut pl = PolyLogger::new();
+    /// pl.add_counted("console", StderrLogger::new(LevelFilter::Info));
+    /// let handle = pl.handle();
+    ///
+    /// pl.log(&Record::builder()
+    ///     .args(format_args!("Counted"))
+    ///     .level(Level::Info)
+    ///     .build());
+    /// handle.disable("console");
+    /// pl.log(&Record::builder()
+    ///     .args(format_args!("Held back"))
+    ///     .level(Level::Info)
+    ///     .build());
+    ///
+    /// let stats = handle.stats("console").unwrap();
+    /// assert_eq!(stats.written(Level::Info), 1);
+    /// assert!(stats.bytes > 0);
+    /// assert_eq!(stats.filtered, 1);
+    ///
+    /// pl.add_named("plain", StderrLogger::new(LevelFilter::Info));
+    /// pl.log(&Record::builder()
+    ///     .args(format_args!("Counted without bytes"))
+    ///     .level(Level::Info)
+    ///     .build());
+    /// let stats = handle.stats("plain").unwrap();
+    /// assert_eq!(stats.written(Level::Info), 1);
+    /// assert_eq!(stats.bytes, 0);
+    /// ```
+    pub fn stats(&self, name: &str) -> Option<StatsSnapshot> {
+        let children = self.shared.children.read().unwrap();
+        children.iter()
+            .find(|c| c.name == name)
+            .map(|c| c.snapshot())
+    }
+
+    /// Returns the stats of every child, in the order they were added
+    pub fn child_stats(&self) -> Vec<(String, StatsSnapshot)> {
+        let children = self.shared.children.read().unwrap();
+        children.iter()
+            .map(|c| (c.name.clone(), c.snapshot()))
+            .collect()
+    }
+
+    fn set_enabled(&self, name: &str, enabled: bool) -> bool {
+        let mut lock = self.shared.children.write().unwrap();
+        let children = Arc::make_mut(&mut lock);
+        let found = match children.iter_mut().find(|c| c.name == name) {
+            Some(child) => {
+                child.enabled = enabled;
+                true
+            },
+            None => false,
+        };
+        self.shared.update_max_level(children);
+        found
+    }
+}
+
+impl fmt::Debug for PolyHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PolyHandle[ loggers:{:?} ]", self.names())
+    }
+}
+
+impl Shared {
+    fn max_level(&self) -> Level {
+        match self.max_level.load(Ordering::Relaxed) {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+
+    // A snapshot of the children, which can be used
+    // without holding the lock
+    fn children(&self) -> Arc<Vec<Child>> {
+        self.children.read().unwrap().clone()
+    }
+
+    // Called with the children write lock held so that
+    // concurrent updates are applied in order
+    fn update_max_level(&self, children: &[Child]) {
+        let max_level = children.iter()
+            .filter(|c| c.enabled)
+            .map(|c| c.max_level)
+            .max()
+            .unwrap_or(Level::Error);
+        self.max_level.store(max_level as usize, Ordering::Relaxed);
+
+        if self.installed.load(Ordering::SeqCst) {
+            log::set_max_level(max_level.to_level_filter());
+        }
+    }
+}
+
+impl Child {
+    fn new(name: &str, logger: Arc<dyn log::Log>, source: Option<Arc<LoggerStats>>) -> Self {
         // Capture the max level before boxing into our
         // vector of loggers.
+        let _probing = sys::probe_levels();
         let test_levels = vec![Level::Warn, Level::Info, Level::Debug, Level::Trace];
         let mut builder = MetadataBuilder::new();
+        let mut max_level = Level::Error;
 
         for level in test_levels {
-            if level > self.max_level {
-                builder.level(level);
-                let metadata = builder.build();
+            builder.level(level);
+            let metadata = builder.build();
 
-                if logger.enabled(&metadata) {
-                    self.max_level = level;
-                }
+            if logger.enabled(&metadata) {
+                max_level = level;
             }
         }
 
-        // Finally add to our vector
-        self.loggers.push(Box::new(logger));
+        Child{
+            name: name.to_string(),
+            logger,
+            max_level,
+            enabled: true,
+            stats: Arc::new(LoggerStats::new()),
+            source,
+        }
+    }
+
+    fn snapshot(&self) -> StatsSnapshot {
+        let mut snapshot = self.stats.snapshot();
+        if let Some(source) = &self.source {
+            snapshot.merge(&source.snapshot());
+        }
+        snapshot
     }
 }
 
 impl fmt::Debug for PolyLogger {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "PolyLogger[ loggers:{} ]", self.loggers.len())
+        let children = self.shared.children.read().unwrap();
+        write!(f, "PolyLogger[ loggers:{} ]", children.len())
     }
 }
 
 impl log::Log for PolyLogger {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
-        metadata.level() <= self.max_level
+        metadata.level() <= self.max_level()
     }
 
     fn log(&self, record: &log::Record) {
-        // Each logger checks if enabled in the log() call
-        self.loggers.iter().for_each(|logger| { 
-            logger.log(record); 
-        });
+        let _in_logger = sys::enter_logger();
+        let children = self.shared.children();
+        for child in children.iter() {
+            if child.enabled && child.logger.enabled(record.metadata()) {
+                child.logger.log(record);
+                if child.source.is_none() {
+                    child.stats.written(record.level(), 0);
+                }
+            } else {
+                child.stats.filtered();
+            }
+        }
     }
 
     fn flush(&self) {
-        self.loggers.iter().for_each(|logger| { 
-            logger.flush();
+        let children = self.shared.children();
+        children.iter().for_each(|child| {
+            child.logger.flush();
         });
     }
 }
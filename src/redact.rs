@@ -0,0 +1,251 @@
+use std::borrow::Cow;
+use regex::{Captures, Regex};
+use super::filter::Filter;
+use super::{mdc, sys};
+
+/// A well-known kind of secret which a [Redact](struct.Redact.html)
+/// filter can find without a pattern being written for it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Detector {
+    /// The token following "Bearer", as in an Authorization header.
+    /// The word "Bearer" itself is kept.
+    BearerToken,
+    /// Runs of 13 to 19 digits, optionally separated by spaces or
+    /// dashes, which pass the Luhn check
+    CreditCard,
+    /// Email addresses
+    Email,
+}
+
+/// A [Filter](trait.Filter.html) which masks secrets in messages,
+/// key-values and the [mdc](mdc/index.html) before they are formatted
+///
+/// Text is matched against regex patterns added with pattern() and
+/// the detectors added with detect(), and each match is replaced by
+/// the mask, which defaults to "[REDACTED]". Key-values and mdc entries
+/// whose key contains a name added with key(), ignoring case, have
+/// their whole value masked, e.g. key("password") masks "db_password".
+///
+/// Records with nothing to mask are passed on unchanged. As a filter
+/// it can wrap one PolyLogger child, so that a file log is redacted
+/// while a local debug log is not.
+///
+/// # Example
+/// ```
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::{CaptureLogger,Detector,FilteredLogger,Redact};
+///
+/// let capture_logger = CaptureLogger::new(LevelFilter::Info);
+/// let capture = capture_logger.handle();
+///
+/// let mut redact = Redact::new();
+/// redact.detect(Detector::BearerToken)
+///       .detect(Detector::CreditCard)
+///       .key("password")
+///       .pattern(r"sk_live_\w+").unwrap()
+///       .mask("***");
+///
+/// let mut logger = FilteredLogger::new(capture_logger);
+/// logger.filter(redact);
+///
+/// let kvs = [("user", "dave"), ("db_password", "hunter2")];
+/// logger.log(&Record::builder()
+///     .args(format_args!("Paid with 4111 1111 1111 1111 using key sk_live_abc123"))
+///     .level(Level::Info)
+///     .key_values(&kvs)
+///     .build());
+/// logger.log(&Record::builder()
+///     .args(format_args!("Authorization: Bearer eyJhbGciOi.J9"))
+///     .level(Level::Info)
+///     .build());
+///
+/// let records = capture.records();
+/// assert_eq!(records[0].message, "Paid with *** using key ***");
+/// assert_eq!(records[0].key_values, vec![
+///     ("user".to_string(), "dave".to_string()),
+///     ("db_password".to_string(), "***".to_string()),
+/// ]);
+/// assert_eq!(records[1].message, "Authorization: Bearer ***");
+/// ```
+///
+/// # Per Child Example
+/// ```
+/// use log::{info,LevelFilter};
+/// use poly_logger::{Detector,FileLogger,FilteredLogger,PolyLogger,Redact,StderrLogger};
+///
+/// let mut redact = Redact::new();
+/// redact.detect(Detector::Email)
+///       .key("secret");
+///
+/// // Only the file log is redacted
+/// let mut file = FileLogger::new(LevelFilter::Info);
+/// file.filename("./test.log");
+/// let mut file = FilteredLogger::new(file.create());
+/// file.filter(redact);
+///
+/// let mut pl = PolyLogger::new();
+/// pl.add_named("file", file);
+/// pl.add_named("console", StderrLogger::new(LevelFilter::Debug));
+/// pl.init().unwrap();
+///
+/// info!(client_secret = "s3cr3t"; "Signed up dave@example.com");
+/// ```
+///
+pub struct Redact {
+    rules: Vec<Rule>,
+    keys: Vec<String>,
+    mask: String,
+}
+
+struct Rule {
+    regex: Regex,
+    detector: Option<Detector>,
+}
+
+impl Redact {
+    /// Instantiates a Redact filter with no rules
+    pub fn new() -> Redact {
+        Redact {
+            rules: Vec::new(),
+            keys: Vec::new(),
+            mask: "[REDACTED]".to_string(),
+        }
+    }
+
+    /// Masks text matching a regular expression. Fails if the
+    /// pattern is not a valid
+    /// [regex](https://docs.rs/regex/latest/regex/#syntax).
+    pub fn pattern(&mut self, pattern: &str) -> Result<&mut Self, regex::Error> {
+        let regex = Regex::new(pattern)?;
+        self.rules.push(Rule { regex, detector: None });
+        Ok(self)
+    }
+
+    /// Masks a well-known kind of secret
+    pub fn detect(&mut self, detector: Detector) -> &mut Self {
+        let pattern = match detector {
+            Detector::BearerToken => r"(?i)\b(bearer\s+)[A-Za-z0-9\-._~+/]+=*",
+            Detector::CreditCard => r"\b\d(?:[ -]?\d){12,18}\b",
+            Detector::Email => r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}\b",
+        };
+        let regex = Regex::new(pattern).expect("Invalid detector pattern");
+        self.rules.push(Rule { regex, detector: Some(detector) });
+        self
+    }
+
+    /// Masks the whole value of key-values and mdc entries whose
+    /// key contains the given name, ignoring case
+    pub fn key(&mut self, name: &str) -> &mut Self {
+        self.keys.push(name.to_lowercase());
+        self
+    }
+
+    /// Sets the text which replaces a secret.
+    /// The default is "[REDACTED]".
+    pub fn mask(&mut self, mask: &str) -> &mut Self {
+        self.mask = mask.to_string();
+        self
+    }
+
+    /// Returns the text with every match of the
+    /// patterns and detectors masked
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for rule in &self.rules {
+            if let Cow::Owned(redacted) = rule.redact(&text, &self.mask) {
+                text = Cow::Owned(redacted);
+            }
+        }
+        text
+    }
+
+    // Returns the value masked or redacted, or None if unchanged
+    fn redact_value(&self, key: &str, value: &str) -> Option<String> {
+        let key = key.to_lowercase();
+        if self.keys.iter().any(|k| key.contains(k.as_str())) {
+            return Some(self.mask.clone());
+        }
+        match self.redact(value) {
+            Cow::Owned(redacted) => Some(redacted),
+            Cow::Borrowed(_) => None,
+        }
+    }
+}
+
+impl Default for Redact {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rule {
+    fn redact<'a>(&self, text: &'a str, mask: &str) -> Cow<'a, str> {
+        let replaced = self.regex.replace_all(text, |caps: &Captures| {
+            let found = &caps[0];
+            match self.detector {
+                Some(Detector::BearerToken) => format!("{}{}", &caps[1], mask),
+                Some(Detector::CreditCard) if !luhn(found) => found.to_string(),
+                _ => mask.to_string(),
+            }
+        });
+        // A credit card rule may match only numbers it leaves as they are
+        match replaced {
+            Cow::Owned(redacted) if redacted == text => Cow::Borrowed(text),
+            replaced => replaced,
+        }
+    }
+}
+
+// True if the digits in the text pass the Luhn checksum
+fn luhn(text: &str) -> bool {
+    let sum: u32 = text.chars().rev()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, d)| match i % 2 {
+            0 => d,
+            _ if d > 4 => d * 2 - 9,
+            _ => d * 2,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+impl Filter for Redact {
+    fn apply(&self, record: &log::Record, next: &mut dyn FnMut(&log::Record)) {
+        let message = record.args().to_string();
+        let redacted = self.redact(&message);
+
+        let mut key_values = sys::key_values(record);
+        let mut kvs_changed = false;
+        for (key, value) in key_values.iter_mut() {
+            if let Some(redacted) = self.redact_value(key, value) {
+                *value = redacted;
+                kvs_changed = true;
+            }
+        }
+
+        // Shadow the mdc entries to mask until the record is passed on
+        let _guards: Vec<_> = mdc::snapshot().iter()
+            .filter_map(|(key, value)| {
+                self.redact_value(key, value).map(|redacted| mdc::scoped(key, &redacted))
+            })
+            .collect();
+
+        if let (Cow::Borrowed(_), false) = (&redacted, kvs_changed) {
+            next(record);
+            return;
+        }
+
+        let kvs: Vec<(&str, &str)> = key_values.iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        next(&log::Record::builder()
+            .args(format_args!("{}", redacted))
+            .metadata(record.metadata().clone())
+            .module_path(record.module_path())
+            .file(record.file())
+            .line(record.line())
+            .key_values(&kvs)
+            .build());
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use log::{Level, LevelFilter, SetLoggerError};
+use super::log_formatter::{LogFormatter, LogFormat};
+use super::log_event::LogEvent;
+use super::flush_guard;
+use super::stats::{CountedLog, LoggerStats};
+use super::sys;
+
+/// Implements a logger which keeps the most recent records in memory
+///
+/// Records are kept as [LogEvent](struct.LogEvent.html)s until either
+/// more than capacity() records or more than max_bytes() bytes of
+/// messages are held, when the oldest are discarded. They are only
+/// formatted when read, so keeping records which are usually thrown
+/// away costs little more than copying them. A [RingBufferHandle](struct.RingBufferHandle.html)
+/// dumps the contents to any Write on demand, e.g. from a panic hook,
+/// and dump_on() dumps them automatically when a record at or above a
+/// given level is logged.
+///
+/// This lets an application keep Trace level context for diagnostics
+/// without writing it anywhere unless something goes wrong.
+///
+/// # Example
+/// ```
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::RingBufferLogger;
+///
+/// let mut logger = RingBufferLogger::new(LevelFilter::Trace);
+/// logger.msg_format("{level} {args}")
+///       .capacity(2);
+/// let handle = logger.handle();
+///
+/// for msg in &["one", "two", "three"] {
+///     logger.log(&Record::builder()
+///         .args(format_args!("{}", msg))
+///         .level(Level::Trace)
+///         .build());
+/// }
+///
+/// let mut dump = Vec::new();
+/// handle.dump(&mut dump).unwrap();
+/// assert_eq!(String::from_utf8(dump).unwrap(), "TRACE two\nTRACE three\n");
+/// ```
+///
+/// # Dump On Error Example
+/// ```no_run
+/// use std::fs::File;
+/// use log::{error,trace,LevelFilter,Level};
+/// use poly_logger::{PolyLogger,RingBufferLogger,StderrLogger};
+///
+/// let mut ring = RingBufferLogger::new(LevelFilter::Trace);
+/// ring.dump_on(Level::Error, File::create("crash.log").unwrap());
+///
+/// let mut pl = PolyLogger::new();
+/// pl.add(ring);
+/// pl.add(StderrLogger::new(LevelFilter::Info));
+/// pl.init().unwrap();
+///
+/// trace!("Only written to crash.log if an error follows");
+/// error!("Something failed");
+/// ```
+///
+pub struct RingBufferLogger {
+    level_filter: LevelFilter,
+    ring: Arc<Mutex<Ring>>,
+    dump_on: Option<(Level, DumpWriter)>,
+    stats: Arc<LoggerStats>,
+}
+
+// Shared so that it can be flushed on shutdown
+type DumpWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// A cloneable handle to the contents of a
+/// [RingBufferLogger](struct.RingBufferLogger.html), which remains
+/// usable after the logger has been moved by init() or into a PolyLogger
+#[derive(Clone)]
+pub struct RingBufferHandle {
+    ring: Arc<Mutex<Ring>>,
+}
+
+struct Ring {
+    events: VecDeque<LogEvent>,
+    bytes: usize,
+    capacity: usize,
+    max_bytes: usize,
+    // Kept with the events, as handles format them when read
+    log_formatter: LogFormatter,
+}
+
+impl RingBufferLogger {
+    /// Instantiates a RingBufferLogger holding up to 1000 records
+    pub fn new(level_filter: LevelFilter) -> RingBufferLogger {
+        let ring = Ring {
+            events: VecDeque::new(),
+            bytes: 0,
+            capacity: 1000,
+            max_bytes: usize::MAX,
+            log_formatter: LogFormatter::new(),
+        };
+        RingBufferLogger {
+            level_filter,
+            ring: Arc::new(Mutex::new(ring)),
+            dump_on: None,
+            stats: Arc::new(LoggerStats::new()),
+        }
+    }
+
+    /// Initializes the log interface using this RingBufferLogger
+    /// as a boxed logger. This moves self so is the last
+    /// method to call on this object.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        log::set_max_level(self.level_filter);
+        log::set_boxed_logger(Box::new(self))
+    }
+
+    /// Sets the output format.
+    /// See [GenLogger::log_format](struct.GenLogger.html#method.log_format)
+    pub fn log_format(&mut self, format: LogFormat) -> &mut Self {
+        self.ring.lock().unwrap().log_formatter.format(format);
+        self
+    }
+
+    /// Sets the timestamp format.
+    /// See [GenLogger::timestamp_format](struct.GenLogger.html#method.timestamp_format)
+    pub fn timestamp_format(&mut self, format: &str) -> &mut Self {
+        self.ring.lock().unwrap().log_formatter.timestamp_format(format);
+        self
+    }
+
+    /// Sets the message format.
+    /// See [GenLogger::msg_format](struct.GenLogger.html#method.msg_format)
+    pub fn msg_format(&mut self, format: &str) -> &mut Self {
+        self.ring.lock().unwrap().log_formatter.msg_format(format);
+        self
+    }
+
+    /// Sets the most records kept. The default is 1000.
+    pub fn capacity(&mut self, records: usize) -> &mut Self {
+        self.ring.lock().unwrap().capacity = records;
+        self
+    }
+
+    /// Sets the most bytes of messages kept, counting the
+    /// message of each record before it is formatted.
+    /// There is no limit by default.
+    pub fn max_bytes(&mut self, bytes: usize) -> &mut Self {
+        self.ring.lock().unwrap().max_bytes = bytes;
+        self
+    }
+
+    /// Dumps and clears the buffer to the given writer whenever
+    /// a record at or above the given level is logged. The dump
+    /// ends with that record.
+    ///
+    /// The writer is also flushed on exit with a
+    /// [FlushGuard](struct.FlushGuard.html).
+    pub fn dump_on<W: Write + Send + 'static>(&mut self, level: Level, writer: W) -> &mut Self {
+        let writer: DumpWriter = Arc::new(Mutex::new(Box::new(writer)));
+        let weak = Arc::downgrade(&writer);
+        flush_guard::on_shutdown(move |_| {
+            match weak.upgrade() {
+                Some(writer) => {
+                    if let Ok(mut writer) = writer.lock() {
+                        let _ = writer.flush();
+                    }
+                    true
+                },
+                None => false,
+            }
+        });
+        self.dump_on = Some((level, writer));
+        self
+    }
+
+    /// Returns a handle for dumping the buffer
+    pub fn handle(&self) -> RingBufferHandle {
+        RingBufferHandle { ring: self.ring.clone() }
+    }
+
+    /// Returns the counts of records kept (as written, with the
+    /// length of their messages as bytes), records
+    /// pushed out of the buffer by newer ones (as dropped) and failed
+    /// dumps. The stats are shared, so they can still be read after
+    /// this logger has been moved by init() or into a PolyLogger.
+    ///
+    /// # Example
+    /// ```
+    /// use log::{Log,Level,LevelFilter,Record};
+    /// use poly_logger::RingBufferLogger;
+    ///
+    /// let mut logger = RingBufferLogger::new(LevelFilter::Info);
+    /// logger.capacity(2);
+    /// let stats = logger.stats();
+    ///
+    /// for _ in 0..3 {
+    ///     logger.log(&Record::builder()
+    ///         .args(format_args!("Kept"))
+    ///         .level(Level::Info)
+    ///         .build());
+    /// }
+    /// assert_eq!(stats.snapshot().written(Level::Info), 3);
+    /// assert_eq!(stats.snapshot().dropped, 1);
+    /// ```
+    pub fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+
+    /// Keeps an event captured earlier, e.g. from a queue, using the
+    /// event's own timestamp. Applies the same level filtering as log().
+    ///
+    /// # Example
+    /// ```
+    /// use log::{Level,LevelFilter,Record};
+    /// use poly_logger::{LogEvent,RingBufferLogger};
+    ///
+    /// let event = LogEvent::from(&Record::builder()
+    ///     .args(format_args!("Deferred"))
+    ///     .level(Level::Info)
+    ///     .build());
+    ///
+    /// let mut logger = RingBufferLogger::new(LevelFilter::Info);
+    /// logger.msg_format("{timestamp} {args}")
+    ///       .timestamp_format("%H:%M:%S%.6f");
+    /// logger.log_event(&event);
+    ///
+    /// let line = format!("{} Deferred", event.timestamp.format("%H:%M:%S%.6f"));
+    /// assert_eq!(logger.handle().lines(), vec![line]);
+    /// ```
+    pub fn log_event(&self, event: &LogEvent) {
+        if event.level <= self.level_filter {
+            self.write(event.clone());
+        } else {
+            self.stats.filtered();
+        }
+    }
+
+    fn write(&self, event: LogEvent) {
+        let level = event.level;
+        self.stats.written(level, event.message.len());
+        let evicted = self.ring.lock().unwrap().push(event);
+        self.stats.dropped(evicted);
+
+        if let Some((dump_level, writer)) = &self.dump_on {
+            if level <= *dump_level {
+                let mut writer = writer.lock().unwrap();
+                if let Err(e) = self.handle().dump_take(&mut **writer) {
+                    eprintln!("Failed to dump the ring buffer: {}", e);
+                    self.stats.write_error();
+                }
+            }
+        }
+    }
+}
+
+impl RingBufferHandle {
+    /// Writes the buffered records to the writer, oldest first,
+    /// one per line. The buffer is left as it is.
+    pub fn dump<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        let lines = self.lines();
+        for line in &lines {
+            writer.write_all(line.as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()
+    }
+
+    /// Returns the buffered records formatted, oldest first
+    pub fn lines(&self) -> Vec<String> {
+        let (events, log_formatter) = {
+            let ring = self.ring.lock().unwrap();
+            (ring.events.iter().cloned().collect(), ring.log_formatter.clone())
+        };
+        format(&log_formatter, events)
+    }
+
+    /// Returns the buffered records formatted and removes
+    /// them, oldest first
+    pub fn take(&self) -> Vec<String> {
+        let (events, log_formatter) = {
+            let mut ring = self.ring.lock().unwrap();
+            ring.bytes = 0;
+            (ring.events.drain(..).collect(), ring.log_formatter.clone())
+        };
+        format(&log_formatter, events)
+    }
+
+    /// Discards the buffered records
+    pub fn clear(&self) {
+        self.take();
+    }
+
+    /// Returns the number of buffered records
+    pub fn len(&self) -> usize {
+        self.ring.lock().unwrap().events.len()
+    }
+
+    /// Returns true if no records are buffered
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Dumps and clears the buffer
+    fn dump_take<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        for line in self.take() {
+            writer.write_all(line.as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()
+    }
+}
+
+impl Ring {
+    // Keeps an event, returning how many older events were discarded
+    fn push(&mut self, event: LogEvent) -> u64 {
+        self.bytes += event.message.len();
+        self.events.push_back(event);
+        let mut evicted = 0;
+        while self.events.len() > self.capacity || self.bytes > self.max_bytes {
+            match self.events.pop_front() {
+                Some(oldest) => self.bytes -= oldest.message.len(),
+                None => break,
+            }
+            evicted += 1;
+        }
+        evicted
+    }
+}
+
+fn format(log_formatter: &LogFormatter, events: Vec<LogEvent>) -> Vec<String> {
+    events.iter()
+        .map(|event| match log_formatter.event_msg(event) {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("Invalid log format: {}", e);
+                log_formatter.default_fields_msg(event)
+            },
+        })
+        .collect()
+}
+
+impl log::Log for RingBufferLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level_filter
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            self.stats.filtered();
+            return;
+        }
+
+        let _in_logger = sys::enter_logger();
+        self.write(LogEvent::from(record));
+    }
+
+    fn flush(&self) {
+        if let Some((_, writer)) = &self.dump_on {
+            let _ = writer.lock().unwrap().flush();
+        }
+    }
+}
+
+impl CountedLog for RingBufferLogger {
+    fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+}
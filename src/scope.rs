@@ -0,0 +1,141 @@
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::panic::Location;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use log::Level;
+
+thread_local! {
+    static SCOPES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+// The level of enter/exit records, or 0 for none
+static SCOPE_LEVEL: AtomicUsize = AtomicUsize::new(0);
+
+/// A named diagnostic scope, which is left when dropped
+///
+/// See [scope](fn.scope.html)
+#[must_use = "the scope is left as soon as it is dropped"]
+pub struct Scope {
+    depth: usize,
+    start: Instant,
+    location: &'static Location<'static>,
+    // The scope must be left on the thread which entered it
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Enters a named scope until the returned guard is dropped
+///
+/// Scopes form a stack per thread, which loggers show with the {scope}
+/// placeholder as the names joined by ">" (e.g. "etl>load_customers>parse").
+/// GenLogger can also indent messages by the scope depth, see
+/// [GenLogger::indent](struct.GenLogger.html#method.indent).
+///
+/// If [set_scope_level](fn.set_scope_level.html) has been called,
+/// entering and leaving a scope are also logged, the latter with the
+/// time spent in the scope.
+///
+/// # Example
+/// ```
+/// use log::{info,Level};
+/// use poly_logger::{scope,set_scope_level,CaptureLogger};
+///
+/// fn parse() {
+///     let _s = scope("parse");
+///     info!("Parsing");
+/// }
+///
+/// let capture = CaptureLogger::capture();
+/// set_scope_level(Some(Level::Debug));
+/// {
+///     let _s = scope("etl");
+///     parse();
+/// }
+/// set_scope_level(None);
+///
+/// let records = capture.records();
+/// assert_eq!(records[0].message, "Entering etl");
+/// assert_eq!(records[2].scope, vec!["etl", "parse"]);
+/// assert_eq!(records[2].message, "Parsing");
+/// assert!(records[3].message.starts_with("Leaving parse after "));
+/// assert_eq!(records[4].key_values[0].0, "elapsed_ms");
+/// assert_eq!(records.len(), 5);
+/// ```
+#[track_caller]
+pub fn scope(name: &str) -> Scope {
+    let depth = SCOPES.with(|s| {
+        let mut scopes = s.borrow_mut();
+        scopes.push(name.to_string());
+        scopes.len() - 1
+    });
+    let scope = Scope {
+        depth,
+        start: Instant::now(),
+        location: Location::caller(),
+        _not_send: PhantomData,
+    };
+    if let Some(level) = scope_level() {
+        scope.log(level, format_args!("Entering {}", name), None);
+    }
+    scope
+}
+
+/// Sets the level at which entering and leaving scopes is logged,
+/// or turns this off with None, which is the default. The records
+/// have the target "poly_logger::scope".
+pub fn set_scope_level(level: Option<Level>) {
+    SCOPE_LEVEL.store(level.map(|l| l as usize).unwrap_or(0), Ordering::Relaxed);
+}
+
+fn scope_level() -> Option<Level> {
+    match SCOPE_LEVEL.load(Ordering::Relaxed) {
+        1 => Some(Level::Error),
+        2 => Some(Level::Warn),
+        3 => Some(Level::Info),
+        4 => Some(Level::Debug),
+        5 => Some(Level::Trace),
+        _ => None,
+    }
+}
+
+// Returns the names of this thread's scopes, outermost first
+pub(crate) fn current() -> Vec<String> {
+    SCOPES.try_with(|s| s.borrow().clone()).unwrap_or_default()
+}
+
+impl Scope {
+    fn log(&self, level: Level, args: std::fmt::Arguments, elapsed: Option<Duration>) {
+        if level > log::max_level() {
+            return;
+        }
+        let elapsed_ms = elapsed.map(|e| e.as_secs_f64() * 1000.0);
+        let kvs = [("elapsed_ms", elapsed_ms)];
+        let kvs: &[(&str, Option<f64>)] = match elapsed_ms {
+            Some(_) => &kvs,
+            None => &[],
+        };
+        log::logger().log(&log::Record::builder()
+            .args(args)
+            .level(level)
+            .target("poly_logger::scope")
+            .file(Some(self.location.file()))
+            .line(Some(self.location.line()))
+            .key_values(&kvs)
+            .build());
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        if let Some(level) = scope_level() {
+            let name = SCOPES.try_with(|s| s.borrow().get(self.depth).cloned())
+                .ok()
+                .flatten()
+                .unwrap_or_default();
+            let elapsed = self.start.elapsed();
+            self.log(level, format_args!("Leaving {} after {:?}", name, elapsed),
+                     Some(elapsed));
+        }
+        let _ = SCOPES.try_with(|s| s.borrow_mut().truncate(self.depth));
+    }
+}
@@ -0,0 +1,312 @@
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use log::Level;
+use super::poly_logger::PolyHandle;
+
+/// Counters kept by a logger, shared so they can be read while
+/// the logger is in use
+///
+/// Every logger in this crate counts what it writes, e.g.
+/// [GenLogger](struct.GenLogger.html#method.stats) and
+/// [TcpLogger](struct.TcpLogger.html#method.stats), and a
+/// [PolyLogger](struct.PolyHandle.html#method.stats) reports the
+/// stats of each child along with what it held back from it. Read
+/// them with snapshot(), or log them periodically with a
+/// [StatsReporter](struct.StatsReporter.html).
+#[derive(Debug, Default)]
+pub struct LoggerStats {
+    written: [AtomicU64; 5],
+    bytes: AtomicU64,
+    filtered: AtomicU64,
+    dropped: AtomicU64,
+    write_errors: AtomicU64,
+    format_errors: AtomicU64,
+    queue_depth: AtomicU64,
+}
+
+/// A logger which keeps [LoggerStats](struct.LoggerStats.html)
+///
+/// Children added to a PolyLogger with
+/// [add_counted](struct.PolyLogger.html#method.add_counted) report
+/// these stats, so that what the child actually wrote, lost or
+/// failed to write is seen through the
+/// [PolyHandle](struct.PolyHandle.html#method.stats).
+pub trait CountedLog: log::Log {
+    /// Returns the stats of this logger
+    fn stats(&self) -> Arc<LoggerStats>;
+}
+
+impl<T: CountedLog + ?Sized> CountedLog for Box<T> {
+    fn stats(&self) -> Arc<LoggerStats> {
+        (**self).stats()
+    }
+}
+
+/// The values of a [LoggerStats](struct.LoggerStats.html) at one time
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    /// Records written, indexed by level from Error to Trace
+    pub written: [u64; 5],
+    /// Bytes written
+    pub bytes: u64,
+    /// Records not written because of their level, or held back
+    /// by a filter or a disabled PolyLogger child
+    pub filtered: u64,
+    /// Records lost, e.g. to a full queue or a failed write
+    pub dropped: u64,
+    /// Failed writes or sends
+    pub write_errors: u64,
+    /// Records which did not match the message format
+    pub format_errors: u64,
+    /// Records waiting in a queue to be sent
+    pub queue_depth: u64,
+}
+
+impl LoggerStats {
+    pub fn new() -> LoggerStats {
+        LoggerStats::default()
+    }
+
+    /// Returns the current values
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let mut written = [0; 5];
+        for (w, count) in written.iter_mut().zip(&self.written) {
+            *w = count.load(Ordering::Relaxed);
+        }
+        StatsSnapshot {
+            written,
+            bytes: self.bytes.load(Ordering::Relaxed),
+            filtered: self.filtered.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            write_errors: self.write_errors.load(Ordering::Relaxed),
+            format_errors: self.format_errors.load(Ordering::Relaxed),
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn written(&self, level: Level, bytes: usize) {
+        self.written[level as usize - 1].fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn filtered(&self) {
+        self.filtered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped(&self, count: u64) {
+        self.dropped.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn write_error(&self) {
+        self.write_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn format_error(&self) {
+        self.format_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn queued(&self) {
+        self.queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dequeued(&self, count: u64) {
+        self.queue_depth.fetch_sub(count, Ordering::Relaxed);
+    }
+
+    // Adds the counts of another logger, but not its queue depth
+    pub(crate) fn absorb(&self, other: &StatsSnapshot) {
+        for (count, w) in self.written.iter().zip(&other.written) {
+            count.fetch_add(*w, Ordering::Relaxed);
+        }
+        self.bytes.fetch_add(other.bytes, Ordering::Relaxed);
+        self.filtered.fetch_add(other.filtered, Ordering::Relaxed);
+        self.dropped.fetch_add(other.dropped, Ordering::Relaxed);
+        self.write_errors.fetch_add(other.write_errors, Ordering::Relaxed);
+        self.format_errors.fetch_add(other.format_errors, Ordering::Relaxed);
+    }
+}
+
+impl StatsSnapshot {
+    /// Returns the records written at the given level
+    pub fn written(&self, level: Level) -> u64 {
+        self.written[level as usize - 1]
+    }
+
+    /// Returns the records written at all levels
+    pub fn total_written(&self) -> u64 {
+        self.written.iter().sum()
+    }
+
+    pub(crate) fn merge(&mut self, other: &StatsSnapshot) {
+        for (w, count) in self.written.iter_mut().zip(&other.written) {
+            *w += count;
+        }
+        self.bytes += other.bytes;
+        self.filtered += other.filtered;
+        self.dropped += other.dropped;
+        self.write_errors += other.write_errors;
+        self.format_errors += other.format_errors;
+        self.queue_depth += other.queue_depth;
+    }
+}
+
+impl fmt::Display for StatsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "written={} (error={} warn={} info={} debug={} trace={}) bytes={} \
+                   filtered={} dropped={} write_errors={} format_errors={} queue_depth={}",
+               self.total_written(), self.written[0], self.written[1], self.written[2],
+               self.written[3], self.written[4], self.bytes, self.filtered, self.dropped,
+               self.write_errors, self.format_errors, self.queue_depth)
+    }
+}
+
+/// Logs the stats of a set of loggers periodically
+///
+/// Each report is a record per logger with the target
+/// "poly_logger::stats", at Info level unless any errors or drops
+/// have occurred since the last report, when it is at Warn level.
+/// The reporter thread stops when the StatsReporter is dropped.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use log::{info,LevelFilter};
+/// use poly_logger::{PolyLogger,StatsReporter,StderrLogger};
+///
+/// let console = StderrLogger::new(LevelFilter::Info);
+/// let console_stats = console.stats();
+///
+/// let mut pl = PolyLogger::new();
+/// pl.add_counted("console", console);
+/// let handle = pl.handle();
+/// pl.init().unwrap();
+///
+/// let mut reporter = StatsReporter::new(Duration::from_millis(100));
+/// reporter.add("stderr", console_stats.clone())
+///         .poly(handle.clone());
+/// let reporter = reporter.start();
+///
+/// info!("Counted");
+/// assert_eq!(console_stats.snapshot().written(log::Level::Info), 1);
+/// assert_eq!(handle.stats("console").unwrap().written(log::Level::Info), 1);
+/// std::thread::sleep(Duration::from_millis(150));
+/// ```
+///
+pub struct StatsReporter {
+    interval: Duration,
+    sources: Vec<Source>,
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+enum Source {
+    Logger(String, Arc<LoggerStats>),
+    Poly(PolyHandle),
+}
+
+impl StatsReporter {
+    /// Instantiates a StatsReporter which reports every interval
+    pub fn new(interval: Duration) -> StatsReporter {
+        StatsReporter {
+            interval,
+            sources: Vec::new(),
+            stop: Arc::new((Mutex::new(false), Condvar::new())),
+            thread: None,
+        }
+    }
+
+    /// Adds the stats of a logger, reported under the given name
+    pub fn add(&mut self, name: &str, stats: Arc<LoggerStats>) -> &mut Self {
+        self.sources.push(Source::Logger(name.to_string(), stats));
+        self
+    }
+
+    /// Adds the per child stats of a PolyLogger, reported
+    /// under the names of the children
+    pub fn poly(&mut self, handle: PolyHandle) -> &mut Self {
+        self.sources.push(Source::Poly(handle));
+        self
+    }
+
+    /// Starts the reporter thread. This moves self so is the
+    /// last method to call before keeping the returned value.
+    pub fn start(mut self) -> StatsReporter {
+        let sources = std::mem::take(&mut self.sources);
+        let interval = self.interval;
+        let stop = self.stop.clone();
+        self.thread = Some(thread::Builder::new()
+            .name("poly_logger_stats".to_string())
+            .spawn(move || report(sources, interval, stop))
+            .expect("Failed to spawn StatsReporter thread"));
+        self
+    }
+
+    /// Stops reporting
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        let (lock, cvar) = &*self.stop;
+        *lock.lock().unwrap() = true;
+        cvar.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for StatsReporter {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// The reporter thread
+fn report(sources: Vec<Source>, interval: Duration,
+          stop: Arc<(Mutex<bool>, Condvar)>) {
+    let mut previous: Vec<(String, StatsSnapshot)> = Vec::new();
+    let mut next = Instant::now() + interval;
+
+    loop {
+        {
+            let (lock, cvar) = &*stop;
+            let mut stopped = lock.lock().unwrap();
+            while !*stopped && Instant::now() < next {
+                let wait = next.saturating_duration_since(Instant::now());
+                stopped = cvar.wait_timeout(stopped, wait).unwrap().0;
+            }
+            if *stopped {
+                return;
+            }
+        }
+        next += interval;
+
+        let mut current = Vec::new();
+        for source in &sources {
+            match source {
+                Source::Logger(name, stats) => current.push((name.clone(), stats.snapshot())),
+                Source::Poly(handle) => current.extend(handle.child_stats()),
+            }
+        }
+
+        for (name, snapshot) in &current {
+            let last = previous.iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, s)| s.clone())
+                .unwrap_or_default();
+            let failing = snapshot.dropped > last.dropped ||
+                snapshot.write_errors > last.write_errors ||
+                snapshot.format_errors > last.format_errors;
+            let level = match failing {
+                true => Level::Warn,
+                false => Level::Info,
+            };
+            log::log!(target: "poly_logger::stats", level, "{}: {}", name, snapshot);
+        }
+        previous = current;
+    }
+}
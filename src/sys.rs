@@ -0,0 +1,158 @@
+use std::cell::Cell;
+use std::sync::OnceLock;
+use std::thread::LocalKey;
+use std::time::{Duration, Instant};
+use log::kv::{self, Key, Value, VisitSource};
+
+// Returns the host name, falling back to "localhost"
+pub fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut buf = [0u8; 256];
+        let rc = unsafe {
+            libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len())
+        };
+        if rc == 0 {
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            if let Ok(name) = std::str::from_utf8(&buf[..len]) {
+                if !name.is_empty() {
+                    return name.to_string();
+                }
+            }
+        }
+    }
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .unwrap_or_else(|_| "localhost".to_string())
+}
+
+// Returns the name of the running executable
+pub fn app_name() -> String {
+    std::env::args().next()
+        .and_then(|arg0| {
+            std::path::Path::new(&arg0).file_name()
+                .map(|name| name.to_string_lossy().to_string())
+        })
+        .unwrap_or_else(|| "-".to_string())
+}
+
+// Maps a log level to a syslog severity
+pub fn syslog_severity(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    }
+}
+
+// Collects the key-values of a record as strings
+pub fn key_values(record: &log::Record) -> Vec<(String, String)> {
+    struct Collect(Vec<(String, String)>);
+
+    impl<'kvs> VisitSource<'kvs> for Collect {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+            self.0.push((key.to_string(), value.to_string()));
+            Ok(())
+        }
+    }
+
+    let mut collect = Collect(Vec::new());
+    let _ = record.key_values().visit(&mut collect);
+    collect.0
+}
+
+// Returns when the process started, or failing that when
+// this was first called
+pub fn process_start() -> Instant {
+    static START: OnceLock<Instant> = OnceLock::new();
+    *START.get_or_init(|| {
+        let now = Instant::now();
+        process_age().and_then(|age| now.checked_sub(age)).unwrap_or(now)
+    })
+}
+
+// Returns when the first logger was created, which {elapsed}
+// is measured from so that every logger agrees on it
+pub fn logging_start() -> Instant {
+    static START: OnceLock<Instant> = OnceLock::new();
+    *START.get_or_init(Instant::now)
+}
+
+// The age of the process from its start time in /proc/self/stat,
+// which is in clock ticks since boot
+#[cfg(target_os = "linux")]
+fn process_age() -> Option<Duration> {
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    let uptime = std::fs::read_to_string("/proc/uptime").ok()?;
+
+    // The command name may contain spaces, so skip past it
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 2..].split(' ').collect();
+    let start_ticks: f64 = fields.get(19)?.parse().ok()?;
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks_per_sec <= 0 {
+        return None;
+    }
+    let uptime: f64 = uptime.split(' ').next()?.parse().ok()?;
+    let age = uptime - start_ticks / ticks_per_sec as f64;
+    Some(Duration::from_secs_f64(age.max(0.0)))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_age() -> Option<Duration> {
+    None
+}
+
+// Holds a thread-local flag set until dropped, so that it is
+// cleared even if the code it guards panics
+pub struct FlagGuard(&'static LocalKey<Cell<bool>>);
+
+impl FlagGuard {
+    // Sets the flag, or returns None if it is already set
+    // or the thread-local has been destroyed
+    pub fn set(flag: &'static LocalKey<Cell<bool>>) -> Option<FlagGuard> {
+        match flag.try_with(|f| f.replace(true)) {
+            Ok(false) => Some(FlagGuard(flag)),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for FlagGuard {
+    fn drop(&mut self) {
+        let _ = self.0.try_with(|f| f.set(false));
+    }
+}
+
+thread_local! {
+    static IN_LOGGER: Cell<bool> = const { Cell::new(false) };
+}
+
+// Marks this thread as inside a logger until the guard is dropped,
+// so that a panic hook does not log through a logger which may be
+// holding its locks. Nested loggers get None and leave it set.
+pub fn enter_logger() -> Option<FlagGuard> {
+    FlagGuard::set(&IN_LOGGER)
+}
+
+// Returns true if this thread is inside a logger
+pub fn in_logger() -> bool {
+    IN_LOGGER.try_with(|f| f.get()).unwrap_or(true)
+}
+
+thread_local! {
+    static PROBING: Cell<bool> = const { Cell::new(false) };
+}
+
+// Marks this thread as asking loggers for the most verbose level
+// they enable for any target, as a PolyLogger does when a child is
+// added, until the guard is dropped
+pub fn probe_levels() -> Option<FlagGuard> {
+    FlagGuard::set(&PROBING)
+}
+
+// Returns true if enabled() is being asked for the most verbose
+// level rather than about a record
+pub fn probing() -> bool {
+    PROBING.try_with(|f| f.get()).unwrap_or(false)
+}
@@ -0,0 +1,558 @@
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket, SocketAddr};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use log::{Level, LevelFilter, SetLoggerError};
+use super::log_formatter::LogFormatter;
+use super::log_event::{LogEvent, RecordEvent, EventFields};
+use super::stats::{CountedLog, LoggerStats};
+use super::sys;
+
+/// Implements a logger which sends records to a syslog server
+///
+/// Records are rendered as
+/// [RFC 5424](https://tools.ietf.org/html/rfc5424) messages, with any
+/// key-values in the record included as structured data, or as legacy
+/// [RFC 3164](https://tools.ietf.org/html/rfc3164) messages. They can
+/// be sent to a local Unix datagram socket such as /dev/log, or to a
+/// UDP or TCP address. TCP uses octet-counting framing and reconnects
+/// if the connection is lost. While the server is down, reconnection
+/// is attempted with an increasing backoff delay of up to 30 seconds,
+/// and records logged in between are dropped rather than waiting.
+/// Writes to a server which stops reading time out, see timeout().
+///
+/// The msg_format applies to the MSG part of the syslog message and
+/// defaults to "{args}", as the syslog header already carries the
+/// timestamp, host and severity.
+///
+/// # Example
+/// Sends a record to a local UDP socket standing in for a syslog server
+/// ```
+/// use std::net::UdpSocket;
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::{Facility,SyslogLogger};
+///
+/// let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let mut logger = SyslogLogger::udp(LevelFilter::Info, server.local_addr().unwrap()).unwrap();
+/// logger.facility(Facility::Local0)
+///       .hostname("myhost")
+///       .app_name("myapp");
+///
+/// // Keys with no valid characters for an SD-PARAM name are left out
+/// let kvs = [("user", "dave"), ("\"]", "dropped")];
+/// logger.log(&Record::builder()
+///     .args(format_args!("Hello syslog"))
+///     .level(Level::Warn)
+///     .key_values(&kvs)
+///     .build());
+///
+/// let mut buf = [0u8; 1024];
+/// let len = server.recv(&mut buf).unwrap();
+/// let msg = String::from_utf8_lossy(&buf[..len]);
+/// assert!(msg.starts_with("<132>1 "));
+/// assert!(msg.contains(" myhost myapp "));
+/// assert!(msg.ends_with(" - [kv@32473 user=\"dave\"] Hello syslog"));
+/// ```
+///
+/// # TCP Outage Example
+/// Records are dropped without blocking while the server is down
+/// ```
+/// use std::net::TcpListener;
+/// use std::time::{Duration, Instant};
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::SyslogLogger;
+///
+/// let server = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let logger = SyslogLogger::tcp(LevelFilter::Info, server.local_addr().unwrap()).unwrap();
+/// drop(server);
+///
+/// let start = Instant::now();
+/// for _ in 0..100 {
+///     logger.log(&Record::builder()
+///         .args(format_args!("Nobody is listening"))
+///         .level(Level::Info)
+///         .build());
+/// }
+/// assert!(start.elapsed() < Duration::from_secs(2));
+/// ```
+///
+/// # Standalone Example
+/// ```no_run
+/// use log::{info,LevelFilter};
+/// use poly_logger::SyslogLogger;
+///
+/// let mut logger = SyslogLogger::unix(LevelFilter::Info, "/dev/log").unwrap();
+/// logger.rfc3164();
+/// logger.init().unwrap();
+/// info!("This is an INFO message to the local syslog daemon");
+/// ```
+///
+pub struct SyslogLogger {
+    level_filter: LevelFilter,
+    facility: Facility,
+    format: SyslogFormat,
+    hostname: String,
+    app_name: String,
+    sd_id: String,
+    log_formatter: LogFormatter,
+    transport: Mutex<Transport>,
+    stats: Arc<LoggerStats>,
+}
+
+/// The syslog message format
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyslogFormat {
+    Rfc5424,
+    Rfc3164,
+}
+
+/// The syslog facility, which is combined with the
+/// severity of each record to form its priority
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Facility {
+    Kern = 0,
+    User = 1,
+    Mail = 2,
+    Daemon = 3,
+    Auth = 4,
+    Syslog = 5,
+    Lpr = 6,
+    News = 7,
+    Uucp = 8,
+    Cron = 9,
+    Authpriv = 10,
+    Ftp = 11,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+enum Transport {
+    #[cfg(unix)]
+    Unix(UnixDatagram, PathBuf),
+    Udp(UdpSocket),
+    Tcp(TcpConnection),
+}
+
+struct TcpConnection {
+    stream: Option<TcpStream>,
+    addr: SocketAddr,
+    connect_timeout: Duration,
+    write_timeout: Duration,
+    // When to next try connecting, and the delay after that
+    next_attempt: Instant,
+    backoff: Duration,
+}
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Returns the syslog severity for a log level
+pub fn syslog_severity(level: Level) -> u8 {
+    sys::syslog_severity(level)
+}
+
+impl SyslogLogger {
+    /// Instantiates a SyslogLogger which sends datagrams to a local
+    /// Unix socket, which is typically "/dev/log"
+    #[cfg(unix)]
+    pub fn unix<P: AsRef<Path>>(level_filter: LevelFilter, path: P) -> io::Result<SyslogLogger> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(&path)?;
+        let transport = Transport::Unix(socket, path.as_ref().to_path_buf());
+        Ok(SyslogLogger::with_transport(level_filter, transport))
+    }
+
+    /// Instantiates a SyslogLogger which sends datagrams to a UDP address
+    pub fn udp<A: ToSocketAddrs>(level_filter: LevelFilter, addr: A) -> io::Result<SyslogLogger> {
+        let addr = resolve(addr)?;
+        let local = match addr {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        Ok(SyslogLogger::with_transport(level_filter, Transport::Udp(socket)))
+    }
+
+    /// Instantiates a SyslogLogger which connects to a TCP address
+    pub fn tcp<A: ToSocketAddrs>(level_filter: LevelFilter, addr: A) -> io::Result<SyslogLogger> {
+        let addr = resolve(addr)?;
+        let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let connection = TcpConnection {
+            stream: Some(stream),
+            addr,
+            connect_timeout: CONNECT_TIMEOUT,
+            write_timeout: WRITE_TIMEOUT,
+            next_attempt: Instant::now(),
+            backoff: MIN_BACKOFF,
+        };
+        Ok(SyslogLogger::with_transport(level_filter, Transport::Tcp(connection)))
+    }
+
+    fn with_transport(level_filter: LevelFilter, transport: Transport) -> SyslogLogger {
+        let mut log_formatter = LogFormatter::new();
+        log_formatter.msg_format("{args}");
+        SyslogLogger {
+            level_filter,
+            facility: Facility::User,
+            format: SyslogFormat::Rfc5424,
+            hostname: sys::hostname(),
+            app_name: sys::app_name(),
+            sd_id: "kv@32473".to_string(),
+            log_formatter,
+            transport: Mutex::new(transport),
+            stats: Arc::new(LoggerStats::new()),
+        }
+    }
+
+    /// Initializes the log interface using this SyslogLogger
+    /// as a boxed logger. This moves self so is the last
+    /// method to call on this object.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        log::set_max_level(self.level_filter);
+        log::set_boxed_logger(Box::new(self))
+    }
+
+    /// Sets the facility. The default is Facility::User
+    pub fn facility(&mut self, facility: Facility) -> &mut Self {
+        self.facility = facility;
+        self
+    }
+
+    /// Uses the legacy RFC 3164 (BSD syslog) format
+    /// rather than the default RFC 5424 format
+    ///
+    /// # Example
+    /// Spaces and control characters are removed from the host
+    /// name and tag, as receivers split the header on spaces
+    /// ```
+    /// use std::net::UdpSocket;
+    /// use log::{Log,LevelFilter,Record,Level};
+    /// use poly_logger::SyslogLogger;
+    ///
+    /// let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// let mut logger = SyslogLogger::udp(LevelFilter::Info, server.local_addr().unwrap()).unwrap();
+    /// logger.rfc3164()
+    ///       .hostname("my host")
+    ///       .app_name("my\napp");
+    ///
+    /// logger.log(&Record::builder()
+    ///     .args(format_args!("Hello syslog"))
+    ///     .level(Level::Info)
+    ///     .build());
+    ///
+    /// let mut buf = [0u8; 1024];
+    /// let len = server.recv(&mut buf).unwrap();
+    /// let msg = String::from_utf8_lossy(&buf[..len]);
+    /// let pid = std::process::id();
+    /// assert!(msg.ends_with(&format!(" myhost myapp[{}]: Hello syslog", pid)));
+    /// ```
+    pub fn rfc3164(&mut self) -> &mut Self {
+        self.format = SyslogFormat::Rfc3164;
+        self
+    }
+
+    /// Sets the message format
+    pub fn format(&mut self, format: SyslogFormat) -> &mut Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets the host name. The default is the name of this host
+    pub fn hostname(&mut self, hostname: &str) -> &mut Self {
+        self.hostname = hostname.to_string();
+        self
+    }
+
+    /// Sets the application name (or tag for RFC 3164).
+    /// The default is the name of the executable.
+    pub fn app_name(&mut self, app_name: &str) -> &mut Self {
+        self.app_name = app_name.to_string();
+        self
+    }
+
+    /// Sets the SD-ID used for the structured data element holding
+    /// the record's key-values. The default is "kv@32473".
+    pub fn structured_data_id(&mut self, sd_id: &str) -> &mut Self {
+        self.sd_id = sd_id.to_string();
+        self
+    }
+
+    /// Sets the connect and write timeouts of the TCP transport, so
+    /// that a stalled server cannot block the logging thread for
+    /// longer. The default is 1s to connect and 5s to write. Has no
+    /// effect on other transports.
+    ///
+    /// # Example
+    /// A server which never reads makes writes fail once the socket
+    /// buffers are full, rather than block
+    /// ```
+    /// use std::net::TcpListener;
+    /// use std::time::{Duration, Instant};
+    /// use log::{Log,LevelFilter,Record,Level};
+    /// use poly_logger::SyslogLogger;
+    ///
+    /// let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    /// let mut logger = SyslogLogger::tcp(LevelFilter::Info, server.local_addr().unwrap()).unwrap();
+    /// logger.timeout(Duration::from_millis(50));
+    /// let stats = logger.stats();
+    ///
+    /// let start = Instant::now();
+    /// let big = "x".repeat(1 << 20);
+    /// while stats.snapshot().write_errors == 0 {
+    ///     logger.log(&Record::builder()
+    ///         .args(format_args!("{}", big))
+    ///         .level(Level::Info)
+    ///         .build());
+    /// }
+    /// assert!(start.elapsed() < Duration::from_secs(10));
+    /// ```
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        if let Transport::Tcp(connection) = self.transport.get_mut().unwrap() {
+            connection.connect_timeout = timeout;
+            connection.write_timeout = timeout;
+            if let Some(stream) = &connection.stream {
+                let _ = stream.set_write_timeout(Some(timeout));
+            }
+        }
+        self
+    }
+
+    /// Sets the format of the MSG part of the syslog message.
+    /// See [GenLogger::msg_format](struct.GenLogger.html#method.msg_format)
+    pub fn msg_format(&mut self, format: &str) -> &mut Self {
+        self.log_formatter.msg_format(format);
+        self
+    }
+
+    /// Sets the timestamp format used by the {timestamp} placeholder
+    /// in the msg_format. This does not affect the syslog header.
+    pub fn timestamp_format(&mut self, format: &str) -> &mut Self {
+        self.log_formatter.timestamp_format(format);
+        self
+    }
+
+    /// Returns the counts of messages sent, filtered and lost by
+    /// this logger. The stats are shared, so they can still be read
+    /// after this logger has been moved by init() or into a PolyLogger.
+    pub fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+
+    /// Sends an event captured earlier, e.g. from a queue, using the
+    /// event's own timestamp. Applies the same level filtering as log().
+    pub fn log_event(&self, event: &LogEvent) {
+        if event.level <= self.level_filter {
+            self.send(event);
+        } else {
+            self.stats.filtered();
+        }
+    }
+
+    fn send(&self, event: &dyn EventFields) {
+        let msg = self.render(event);
+        let mut transport = self.transport.lock().unwrap();
+        match transport.send(msg.as_bytes(), &self.stats) {
+            Ok(()) => self.stats.written(event.level(), msg.len()),
+            // Dropped quietly until the next reconnection attempt
+            Err(e) if e.kind() == io::ErrorKind::NotConnected => self.stats.dropped(1),
+            Err(e) => {
+                eprintln!("Failed to send syslog message: {}", e);
+                self.stats.dropped(1);
+            },
+        }
+    }
+
+    // Renders a record as a syslog message
+    fn render(&self, event: &dyn EventFields) -> String {
+        let msg = match self.log_formatter.fields_msg(event) {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("Invalid log format: {}", e);
+                self.stats.format_error();
+                event.message().to_string()
+            },
+        };
+        let pri = (self.facility as u8) * 8 + syslog_severity(event.level());
+        let now = event.timestamp();
+
+        match self.format {
+            SyslogFormat::Rfc5424 => {
+                format!("<{}>1 {} {} {} {} - {} {}",
+                        pri,
+                        now.to_rfc3339_opts(chrono::SecondsFormat::Micros, false),
+                        header_field(&self.hostname, 255),
+                        header_field(&self.app_name, 48),
+                        std::process::id(),
+                        self.structured_data(event),
+                        msg)
+            },
+            SyslogFormat::Rfc3164 => {
+                format!("<{}>{} {} {}[{}]: {}",
+                        pri,
+                        now.format("%b %e %H:%M:%S"),
+                        header_field(&self.hostname, 255),
+                        header_field(&self.app_name, 32),
+                        std::process::id(),
+                        msg)
+            },
+        }
+    }
+
+    // RFC 5424 structured data holding the record's key-values
+    fn structured_data(&self, event: &dyn EventFields) -> String {
+        let kvs = event.key_values();
+        if kvs.is_empty() {
+            return "-".to_string();
+        }
+
+        let mut sd = format!("[{}", self.sd_id);
+        for (key, value) in kvs {
+            let name: String = key.chars()
+                .filter(|c| c.is_ascii_graphic() && !"= ]\"".contains(*c))
+                .take(32)
+                .collect();
+            if name.is_empty() {
+                continue;
+            }
+            let mut escaped = String::with_capacity(value.len());
+            for c in value.chars() {
+                if c == '"' || c == '\\' || c == ']' {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            }
+            sd.push_str(&format!(" {}=\"{}\"", name, escaped));
+        }
+        sd.push(']');
+        sd
+    }
+}
+
+impl Transport {
+    // Sends a message, counting each failed write or connection
+    fn send(&mut self, msg: &[u8], stats: &LoggerStats) -> io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Transport::Unix(socket, path) => {
+                if socket.send(msg).is_err() {
+                    stats.write_error();
+                    // The syslog daemon may have restarted
+                    socket.connect(path)?;
+                    socket.send(msg).inspect_err(|_| stats.write_error())?;
+                }
+                Ok(())
+            },
+            Transport::Udp(socket) => {
+                socket.send(msg).map(|_| ()).inspect_err(|_| stats.write_error())
+            },
+            Transport::Tcp(connection) => connection.send(msg, stats),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Transport::Tcp(TcpConnection { stream: Some(stream), .. }) => stream.flush(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl TcpConnection {
+    fn send(&mut self, msg: &[u8], stats: &LoggerStats) -> io::Result<()> {
+        // Octet-counting framing as per RFC 6587
+        let mut frame = format!("{} ", msg.len()).into_bytes();
+        frame.extend_from_slice(msg);
+
+        if let Some(s) = &mut self.stream {
+            if s.write_all(&frame).is_ok() {
+                return Ok(());
+            }
+            stats.write_error();
+        }
+
+        // Reconnect once before giving up on this message,
+        // unless a recent attempt failed
+        self.stream = None;
+        let now = Instant::now();
+        if now < self.next_attempt {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "Waiting to reconnect"));
+        }
+        let connected = TcpStream::connect_timeout(&self.addr, self.connect_timeout)
+            .and_then(|mut s| {
+                s.set_write_timeout(Some(self.write_timeout))?;
+                s.write_all(&frame).map(|_| s)
+            });
+        match connected {
+            Ok(s) => {
+                self.stream = Some(s);
+                self.backoff = MIN_BACKOFF;
+                Ok(())
+            },
+            Err(e) => {
+                stats.write_error();
+                self.next_attempt = Instant::now() + self.backoff;
+                self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+                Err(e)
+            },
+        }
+    }
+}
+
+// Header fields must be printable ASCII with no spaces
+fn header_field(value: &str, max_len: usize) -> String {
+    let field: String = value.chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max_len)
+        .collect();
+    match field.is_empty() {
+        true => "-".to_string(),
+        false => field,
+    }
+}
+
+fn resolve<A: ToSocketAddrs>(addr: A) -> io::Result<SocketAddr> {
+    addr.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "No address to send syslog messages to")
+    })
+}
+
+impl log::Log for SyslogLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level_filter
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            self.stats.filtered();
+            return;
+        }
+
+        let _in_logger = sys::enter_logger();
+        self.send(&RecordEvent::new(record));
+    }
+
+    fn flush(&self) {
+        let mut transport = self.transport.lock().unwrap();
+        let _ = transport.flush();
+    }
+}
+
+impl CountedLog for SyslogLogger {
+    fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+}
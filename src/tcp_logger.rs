@@ -0,0 +1,502 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use log::{Level, LevelFilter, SetLoggerError};
+use super::log_formatter::{LogFormatter, LogFormat};
+use super::log_event::{LogEvent, RecordEvent, EventFields};
+use super::flush_guard;
+use super::stats::{CountedLog, LoggerStats};
+use super::sys;
+
+/// Implements a logger which streams formatted lines to a remote
+/// collector over TCP
+///
+/// The connection is made when the first record is logged. If it
+/// cannot be made, or is lost, the logger reconnects with exponential
+/// backoff. Lines logged while disconnected are buffered in memory up
+/// to buffer_size() bytes and replayed in order once reconnected. When
+/// the memory buffer is full the oldest lines are either dropped or, if
+/// a spool_file() is set, moved to the spool file to be replayed first.
+///
+/// Note that all of this happens in the thread doing the logging.
+///
+/// On exit with a [FlushGuard](struct.FlushGuard.html), buffered lines
+/// are sent if the collector can be reached in time, and are otherwise
+/// moved to the spool file, if any, for the next run to replay.
+///
+/// Each line ends with the delimiter(), which is "\n" unless the
+/// log_format() is LogFormat::Gelf, where Graylog expects "\0".
+///
+/// # Example
+/// Logs to a local TcpListener which only starts listening after the
+/// first two records were buffered
+/// ```
+/// use std::io::{BufRead,BufReader};
+/// use std::net::TcpListener;
+/// use std::time::Duration;
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::TcpLogger;
+///
+/// // Find a free port
+/// let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+///
+/// let mut logger = TcpLogger::new(LevelFilter::Info, addr).unwrap();
+/// logger.msg_format("{level} {args}")
+///       .backoff(Duration::from_millis(10), Duration::from_millis(10));
+///
+/// let log = |msg| logger.log(&Record::builder()
+///     .args(format_args!("{}", msg))
+///     .level(Level::Info)
+///     .build());
+/// log("one");
+/// log("two");
+///
+/// let listener = TcpListener::bind(addr).unwrap();
+/// std::thread::sleep(Duration::from_millis(20));
+/// log("three");
+///
+/// let (stream, _) = listener.accept().unwrap();
+/// let lines: Vec<String> = BufReader::new(stream).lines().take(3)
+///     .map(|l| l.unwrap())
+///     .collect();
+/// assert_eq!(lines, vec!["INFO one", "INFO two", "INFO three"]);
+/// ```
+///
+pub struct TcpLogger {
+    level_filter: LevelFilter,
+    log_formatter: LogFormatter,
+    delimiter: Vec<u8>,
+    state: Arc<Mutex<TcpState>>,
+    stats: Arc<LoggerStats>,
+}
+
+// Connection and buffer state
+struct TcpState {
+    addr: SocketAddr,
+    stream: Option<TcpStream>,
+    connect_timeout: Duration,
+    write_timeout: Duration,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    backoff: Duration,
+    next_attempt: Instant,
+    buffer: VecDeque<(Level, Vec<u8>)>,
+    buffered: usize,
+    buffer_size: usize,
+    spool_path: Option<PathBuf>,
+    spool: Option<File>,
+    dropped: u64,
+    stats: Arc<LoggerStats>,
+}
+
+impl TcpLogger {
+    /// Instantiates a TcpLogger for the given collector address.
+    /// Fails only if the address cannot be resolved.
+    pub fn new<A: ToSocketAddrs>(level_filter: LevelFilter, addr: A) -> io::Result<TcpLogger> {
+        let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "No address to send log records to")
+        })?;
+        let stats = Arc::new(LoggerStats::new());
+        let state = TcpState {
+            addr,
+            stream: None,
+            connect_timeout: Duration::from_secs(5),
+            write_timeout: Duration::from_secs(5),
+            min_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            backoff: Duration::from_millis(100),
+            next_attempt: Instant::now(),
+            buffer: VecDeque::new(),
+            buffered: 0,
+            buffer_size: 1024 * 1024,
+            spool_path: None,
+            spool: None,
+            dropped: 0,
+            stats: stats.clone(),
+        };
+        let state = Arc::new(Mutex::new(state));
+
+        // Send or spool the buffer on shutdown, for as long as this logger lives
+        let weak = Arc::downgrade(&state);
+        flush_guard::on_shutdown(move |deadline| {
+            match weak.upgrade() {
+                Some(state) => {
+                    if let Ok(mut state) = state.lock() {
+                        state.shutdown(deadline);
+                    }
+                    true
+                },
+                None => false,
+            }
+        });
+
+        Ok(TcpLogger {
+            level_filter,
+            log_formatter: LogFormatter::new(),
+            delimiter: b"\n".to_vec(),
+            state,
+            stats,
+        })
+    }
+
+    /// Initializes the log interface using this TcpLogger
+    /// as a boxed logger. This moves self so is the last
+    /// method to call on this object.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        log::set_max_level(self.level_filter);
+        log::set_boxed_logger(Box::new(self))
+    }
+
+    /// Sets the output format.
+    /// See [GenLogger::log_format](struct.GenLogger.html#method.log_format)
+    ///
+    /// LogFormat::Gelf also sets the delimiter to "\0".
+    pub fn log_format(&mut self, format: LogFormat) -> &mut Self {
+        self.log_formatter.format(format);
+        self.delimiter = match format {
+            LogFormat::Gelf => b"\0".to_vec(),
+            _ => b"\n".to_vec(),
+        };
+        self
+    }
+
+    /// Sets the bytes written after each record. The default is "\n".
+    pub fn delimiter(&mut self, delimiter: &[u8]) -> &mut Self {
+        self.delimiter = delimiter.to_vec();
+        self
+    }
+
+    /// Sets the timestamp format.
+    /// See [GenLogger::timestamp_format](struct.GenLogger.html#method.timestamp_format)
+    pub fn timestamp_format(&mut self, format: &str) -> &mut Self {
+        self.log_formatter.timestamp_format(format);
+        self
+    }
+
+    /// Sets the message format.
+    /// See [GenLogger::msg_format](struct.GenLogger.html#method.msg_format)
+    pub fn msg_format(&mut self, format: &str) -> &mut Self {
+        self.log_formatter.msg_format(format);
+        self
+    }
+
+    /// Sets the minimum and maximum delay between reconnection
+    /// attempts. The delay doubles after each failed attempt.
+    /// The default is 100ms to 30s.
+    pub fn backoff(&mut self, min: Duration, max: Duration) -> &mut Self {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.min_backoff = min;
+            state.max_backoff = max.max(min);
+            state.backoff = min;
+        }
+        self
+    }
+
+    /// Sets the connect and write timeouts. The default is 5s.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.connect_timeout = timeout;
+            state.write_timeout = timeout;
+        }
+        self
+    }
+
+    /// Sets the number of bytes buffered in memory while
+    /// disconnected. The default is 1MiB.
+    pub fn buffer_size(&mut self, bytes: usize) -> &mut Self {
+        self.state.lock().unwrap().buffer_size = bytes;
+        self
+    }
+
+    /// Sets a file to spill lines to when the memory buffer is full.
+    /// Any lines left in the file from a previous run are replayed
+    /// on the first connection.
+    ///
+    /// # Example
+    /// Lines still buffered on exit are moved to the spool file
+    /// ```
+    /// use std::net::TcpListener;
+    /// use std::time::Duration;
+    /// use log::{info,LevelFilter};
+    /// use poly_logger::{PolyLogger,TcpLogger};
+    ///
+    /// // A port with nothing listening on it
+    /// let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    /// let spool = std::env::temp_dir().join(format!("poly_logger_spool_{}.log", addr.port()));
+    ///
+    /// let mut logger = TcpLogger::new(LevelFilter::Info, addr).unwrap();
+    /// logger.msg_format("{args}")
+    ///       .spool_file(&spool);
+    /// let mut pl = PolyLogger::new();
+    /// pl.add(logger);
+    /// let mut guard = pl.init_with_guard().unwrap();
+    /// guard.timeout(Duration::from_millis(500));
+    ///
+    /// info!("one");
+    /// info!("two");
+    /// drop(guard);
+    /// assert_eq!(std::fs::read_to_string(&spool).unwrap(), "one\ntwo\n");
+    /// std::fs::remove_file(&spool).unwrap();
+    /// ```
+    pub fn spool_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.state.lock().unwrap().spool_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Returns the counts of lines sent or moved to the spool file
+    /// (as written), lines lost because the buffer was full (as
+    /// dropped), failed writes and lines waiting in the memory
+    /// buffer. The stats are shared, so they can still be read
+    /// after this logger has been moved by init().
+    ///
+    /// # Example
+    /// ```
+    /// use std::net::TcpListener;
+    /// use log::{Log,LevelFilter,Record,Level};
+    /// use poly_logger::TcpLogger;
+    ///
+    /// // A port with nothing listening on it
+    /// let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    ///
+    /// let mut logger = TcpLogger::new(LevelFilter::Info, addr).unwrap();
+    /// logger.msg_format("{args}")
+    ///       .buffer_size(8);
+    /// let stats = logger.stats();
+    ///
+    /// for msg in &["one", "two", "three"] {
+    ///     logger.log(&Record::builder()
+    ///         .args(format_args!("{}", msg))
+    ///         .level(Level::Info)
+    ///         .build());
+    /// }
+    /// let snapshot = stats.snapshot();
+    /// assert_eq!(snapshot.dropped, 2);
+    /// assert_eq!(snapshot.queue_depth, 1);
+    /// ```
+    pub fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+
+    /// Sends an event captured earlier, e.g. from a queue, using the
+    /// event's own timestamp. Applies the same level filtering as log().
+    pub fn log_event(&self, event: &LogEvent) {
+        if event.level <= self.level_filter {
+            let line = self.line(event);
+            self.state.lock().unwrap().send(event.level, line);
+        } else {
+            self.stats.filtered();
+        }
+    }
+
+    fn line(&self, event: &dyn EventFields) -> Vec<u8> {
+        let msg = match self.log_formatter.fields_msg(event) {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("Invalid log format: {}", e);
+                self.stats.format_error();
+                self.log_formatter.default_fields_msg(event)
+            },
+        };
+        let mut line = msg.into_bytes();
+        line.extend_from_slice(&self.delimiter);
+        line
+    }
+}
+
+impl TcpState {
+    fn send(&mut self, level: Level, line: Vec<u8>) {
+        self.connect();
+        if self.stream.is_some() && self.buffer.is_empty() {
+            let stream = self.stream.as_mut().unwrap();
+            if stream.write_all(&line).is_ok() {
+                self.stats.written(level, line.len());
+                return;
+            }
+            self.stats.write_error();
+            self.disconnect();
+        }
+        self.push(level, line);
+    }
+
+    // Connects if disconnected and the backoff delay has passed,
+    // then replays anything that was buffered
+    fn connect(&mut self) {
+        if self.stream.is_some() || Instant::now() < self.next_attempt {
+            return;
+        }
+
+        match TcpStream::connect_timeout(&self.addr, self.connect_timeout) {
+            Ok(stream) => {
+                let _ = stream.set_write_timeout(Some(self.write_timeout));
+                let _ = stream.set_nodelay(true);
+                self.stream = Some(stream);
+                self.backoff = self.min_backoff;
+                if self.dropped > 0 {
+                    eprintln!("TcpLogger dropped {} records while disconnected", self.dropped);
+                    self.dropped = 0;
+                }
+                if let Err(e) = self.replay() {
+                    eprintln!("TcpLogger failed to replay buffered records: {}", e);
+                    self.stats.write_error();
+                    self.disconnect();
+                }
+            },
+            Err(_) => {
+                self.next_attempt = Instant::now() + self.backoff;
+                self.backoff = (self.backoff * 2).min(self.max_backoff);
+            },
+        }
+    }
+
+    // Makes a last attempt to send the buffer before the deadline,
+    // then moves anything left to the spool file
+    fn shutdown(&mut self, deadline: Instant) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if !self.buffer.is_empty() && self.stream.is_none() && !remaining.is_zero() {
+            self.connect_timeout = self.connect_timeout.min(remaining);
+            self.write_timeout = self.write_timeout.min(remaining);
+            self.next_attempt = Instant::now();
+            self.connect();
+        }
+        if let Some(stream) = &mut self.stream {
+            let _ = stream.flush();
+        }
+
+        while let Some((level, line)) = self.buffer.pop_front() {
+            self.buffered -= line.len();
+            self.stats.dequeued(1);
+            match self.spool(&line) {
+                Ok(()) => self.stats.written(level, line.len()),
+                Err(_) => {
+                    self.dropped += 1;
+                    self.stats.dropped(1);
+                },
+            }
+        }
+        if let Some(spool) = &mut self.spool {
+            let _ = spool.flush();
+        }
+        if self.dropped > 0 {
+            eprintln!("TcpLogger dropped {} records while disconnected", self.dropped);
+            self.dropped = 0;
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.stream = None;
+        self.next_attempt = Instant::now() + self.backoff;
+        self.backoff = (self.backoff * 2).min(self.max_backoff);
+    }
+
+    // Sends the spool file and then the memory buffer. Lines are
+    // only removed from the memory buffer once sent.
+    fn replay(&mut self) -> io::Result<()> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => return Ok(()),
+        };
+
+        if let Some(path) = &self.spool_path {
+            if self.spool.is_none() && path.exists() {
+                self.spool = Some(OpenOptions::new().read(true).append(true).open(path)?);
+            }
+            if let Some(spool) = &mut self.spool {
+                let mut spooled = Vec::new();
+                spool.seek(SeekFrom::Start(0))?;
+                spool.read_to_end(&mut spooled)?;
+                stream.write_all(&spooled)?;
+                spool.set_len(0)?;
+            }
+        }
+
+        while let Some((level, line)) = self.buffer.front() {
+            stream.write_all(line)?;
+            self.buffered -= line.len();
+            self.stats.written(*level, line.len());
+            self.stats.dequeued(1);
+            self.buffer.pop_front();
+        }
+        Ok(())
+    }
+
+    // Buffers a line, making room by spooling or dropping the oldest lines
+    fn push(&mut self, level: Level, line: Vec<u8>) {
+        self.buffered += line.len();
+        self.buffer.push_back((level, line));
+        self.stats.queued();
+
+        while self.buffered > self.buffer_size {
+            let (level, oldest) = match self.buffer.pop_front() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            self.buffered -= oldest.len();
+            self.stats.dequeued(1);
+            match self.spool(&oldest) {
+                Ok(()) => self.stats.written(level, oldest.len()),
+                Err(e) => {
+                    if self.spool_path.is_some() {
+                        eprintln!("TcpLogger failed to write spool file: {}", e);
+                        self.stats.write_error();
+                    }
+                    self.dropped += 1;
+                    self.stats.dropped(1);
+                },
+            }
+        }
+    }
+
+    fn spool(&mut self, line: &[u8]) -> io::Result<()> {
+        let path = match &self.spool_path {
+            Some(path) => path,
+            None => return Err(io::Error::other("No spool file")),
+        };
+        if self.spool.is_none() {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+            self.spool = Some(file);
+        }
+        self.spool.as_mut().unwrap().write_all(line)
+    }
+}
+
+impl log::Log for TcpLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level_filter
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            self.stats.filtered();
+            return;
+        }
+
+        let _in_logger = sys::enter_logger();
+        let line = self.line(&RecordEvent::new(record));
+        self.state.lock().unwrap().send(record.level(), line);
+    }
+
+    fn flush(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.connect();
+        if let Some(stream) = &mut state.stream {
+            if stream.flush().is_err() {
+                state.disconnect();
+            }
+        }
+    }
+}
+
+impl CountedLog for TcpLogger {
+    fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+}
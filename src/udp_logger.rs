@@ -0,0 +1,448 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use flate2::Compression;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use log::{LevelFilter, SetLoggerError};
+use super::log_formatter::{LogFormatter, LogFormat};
+use super::log_event::{LogEvent, RecordEvent, EventFields};
+use super::stats::{CountedLog, LoggerStats};
+use super::sys;
+
+/// Implements a logger which sends each record as a UDP datagram
+///
+/// Records larger than the mtu() are either truncated, ending with
+/// a truncation marker, or split over several datagrams. Send failures
+/// are counted in the stats() rather than reported, as befits
+/// fire-and-forget logging.
+///
+/// With LogFormat::Gelf each record may be compressed and, if still
+/// larger than the mtu, is sent as up to 128
+/// [GELF chunks](https://go2docs.graylog.org/current/getting_in_log_data/gelf.html)
+/// instead of being truncated or split. Each chunk, including its 12
+/// byte header, fits in the mtu. Records needing more chunks, or an
+/// mtu too small for a chunk header, are counted as write errors.
+///
+/// # Example
+/// ```
+/// use std::net::UdpSocket;
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::UdpLogger;
+///
+/// let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let mut logger = UdpLogger::new(LevelFilter::Info, collector.local_addr().unwrap()).unwrap();
+/// logger.msg_format("{level} {args}")
+///       .mtu(32);
+///
+/// logger.log(&Record::builder()
+///     .args(format_args!("A message which is too long for one datagram"))
+///     .level(Level::Info)
+///     .build());
+///
+/// let mut buf = [0u8; 64];
+/// let len = collector.recv(&mut buf).unwrap();
+/// assert_eq!(&buf[..len], b"INFO A message whi...[truncated]");
+/// assert_eq!(logger.stats().snapshot().write_errors, 0);
+/// ```
+///
+/// # GELF Example
+/// ```
+/// use std::net::UdpSocket;
+/// use log::{Log,LevelFilter,Record,Level};
+/// use poly_logger::{GelfCompression,LogFormat,UdpLogger};
+///
+/// let graylog = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let mut logger = UdpLogger::new(LevelFilter::Info, graylog.local_addr().unwrap()).unwrap();
+/// logger.log_format(LogFormat::Gelf)
+///       .compression(GelfCompression::None)
+///       .mtu(100);
+///
+/// logger.log(&Record::builder()
+///     .args(format_args!("{}", "x".repeat(200)))
+///     .level(Level::Warn)
+///     .build());
+///
+/// // Reassemble the chunks
+/// let mut buf = [0u8; 128];
+/// let mut chunks = Vec::new();
+/// loop {
+///     let len = graylog.recv(&mut buf).unwrap();
+///     assert_eq!(&buf[..2], &[0x1e, 0x0f]);
+///     chunks.push(buf[12..len].to_vec());
+///     if chunks.len() == buf[11] as usize {
+///         break;
+///     }
+///     // Every chunk but the last fills the mtu, header included
+///     assert_eq!(len, 100);
+/// }
+/// let gelf: serde_json::Value = serde_json::from_slice(&chunks.concat()).unwrap();
+/// assert_eq!(gelf["version"], "1.1");
+/// assert_eq!(gelf["level"], 4);
+/// assert_eq!(gelf["short_message"], "x".repeat(200));
+/// ```
+///
+pub struct UdpLogger {
+    level_filter: LevelFilter,
+    log_formatter: LogFormatter,
+    socket: UdpSocket,
+    mtu: usize,
+    oversize: Oversize,
+    marker: String,
+    gelf: bool,
+    compression: GelfCompression,
+    stats: Arc<LoggerStats>,
+}
+
+/// What to do with records larger than the mtu of a
+/// [UdpLogger](struct.UdpLogger.html)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Oversize {
+    /// Truncate the record and append the truncation marker
+    Truncate,
+    /// Send the record in several datagrams
+    Split,
+}
+
+/// How a [UdpLogger](struct.UdpLogger.html) compresses GELF records
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GelfCompression {
+    None,
+    Gzip,
+    Zlib,
+}
+
+// GELF limits a message to 128 chunks, each with a 12 byte header
+const GELF_MAX_CHUNKS: usize = 128;
+const GELF_CHUNK_HEADER: usize = 12;
+
+impl UdpLogger {
+    /// Instantiates a UdpLogger which sends to the given address
+    pub fn new<A: ToSocketAddrs>(level_filter: LevelFilter, addr: A) -> io::Result<UdpLogger> {
+        let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "No address to send log records to")
+        })?;
+        let local = match addr {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+
+        Ok(UdpLogger {
+            level_filter,
+            log_formatter: LogFormatter::new(),
+            socket,
+            mtu: 1472,
+            oversize: Oversize::Truncate,
+            marker: "...[truncated]".to_string(),
+            gelf: false,
+            compression: GelfCompression::Gzip,
+            stats: Arc::new(LoggerStats::new()),
+        })
+    }
+
+    /// Initializes the log interface using this UdpLogger
+    /// as a boxed logger. This moves self so is the last
+    /// method to call on this object.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        log::set_max_level(self.level_filter);
+        log::set_boxed_logger(Box::new(self))
+    }
+
+    /// Sets the output format.
+    /// See [GenLogger::log_format](struct.GenLogger.html#method.log_format)
+    pub fn log_format(&mut self, format: LogFormat) -> &mut Self {
+        self.log_formatter.format(format);
+        self.gelf = format == LogFormat::Gelf;
+        self
+    }
+
+    /// Sets the timestamp format.
+    /// See [GenLogger::timestamp_format](struct.GenLogger.html#method.timestamp_format)
+    pub fn timestamp_format(&mut self, format: &str) -> &mut Self {
+        self.log_formatter.timestamp_format(format);
+        self
+    }
+
+    /// Sets the message format.
+    /// See [GenLogger::msg_format](struct.GenLogger.html#method.msg_format)
+    pub fn msg_format(&mut self, format: &str) -> &mut Self {
+        self.log_formatter.msg_format(format);
+        self
+    }
+
+    /// Sets the largest datagram payload in bytes. The default of
+    /// 1472 fits in a single Ethernet frame.
+    pub fn mtu(&mut self, mtu: usize) -> &mut Self {
+        self.mtu = mtu.max(1);
+        self
+    }
+
+    /// Sets what to do with records larger than the mtu.
+    /// The default is Oversize::Truncate.
+    ///
+    /// # Example
+    /// ```
+    /// use std::net::UdpSocket;
+    /// use log::{Log,LevelFilter,Record,Level};
+    /// use poly_logger::{Oversize,UdpLogger};
+    ///
+    /// let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// let mut logger = UdpLogger::new(LevelFilter::Info, collector.local_addr().unwrap()).unwrap();
+    /// logger.msg_format("{args}")
+    ///       .mtu(8)
+    ///       .oversize(Oversize::Split);
+    /// let log = |msg: &str| logger.log(&Record::builder()
+    ///     .args(format_args!("{}", msg))
+    ///     .level(Level::Info)
+    ///     .build());
+    ///
+    /// let mut buf = [0u8; 64];
+    /// let mut recv = || {
+    ///     let len = collector.recv(&mut buf).unwrap();
+    ///     String::from_utf8_lossy(&buf[..len]).to_string()
+    /// };
+    ///
+    /// // A record of exactly the mtu fits in one datagram
+    /// log("12345678");
+    /// assert_eq!(recv(), "12345678");
+    ///
+    /// log("123456789");
+    /// assert_eq!(recv(), "12345678");
+    /// assert_eq!(recv(), "9");
+    /// ```
+    pub fn oversize(&mut self, oversize: Oversize) -> &mut Self {
+        self.oversize = oversize;
+        self
+    }
+
+    /// Sets the marker appended to truncated records.
+    /// The default is "...[truncated]". A marker longer than the
+    /// mtu is truncated too, so that datagrams never exceed the mtu.
+    ///
+    /// # Example
+    /// ```
+    /// use std::net::UdpSocket;
+    /// use log::{Log,LevelFilter,Record,Level};
+    /// use poly_logger::UdpLogger;
+    ///
+    /// let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// let mut logger = UdpLogger::new(LevelFilter::Info, collector.local_addr().unwrap()).unwrap();
+    /// logger.msg_format("{args}")
+    ///       .mtu(8);
+    ///
+    /// logger.log(&Record::builder()
+    ///     .args(format_args!("Longer than eight bytes"))
+    ///     .level(Level::Info)
+    ///     .build());
+    ///
+    /// let mut buf = [0u8; 64];
+    /// let len = collector.recv(&mut buf).unwrap();
+    /// assert_eq!(&buf[..len], b"...[trun");
+    /// ```
+    pub fn truncation_marker(&mut self, marker: &str) -> &mut Self {
+        self.marker = marker.to_string();
+        self
+    }
+
+    /// Sets the compression of GELF records. The default is
+    /// GelfCompression::Gzip. Has no effect on other formats.
+    pub fn compression(&mut self, compression: GelfCompression) -> &mut Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Returns the counts of records sent (as written), records of
+    /// which any datagram could not be sent (as dropped) and datagrams
+    /// which could not be sent (as write errors). The stats are shared, so they can still be read
+    /// after this logger has been moved by init() or into a PolyLogger.
+    pub fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+
+    /// Sends an event captured earlier, e.g. from a queue, using the
+    /// event's own timestamp. Applies the same level filtering as log().
+    pub fn log_event(&self, event: &LogEvent) {
+        if event.level <= self.level_filter {
+            self.write(event);
+        } else {
+            self.stats.filtered();
+        }
+    }
+
+    // Sends a datagram, returning the bytes sent
+    fn send(&self, datagram: &[u8]) -> Option<usize> {
+        match self.socket.send(datagram) {
+            Ok(sent) => Some(sent),
+            Err(_) => {
+                self.stats.write_error();
+                None
+            },
+        }
+    }
+
+    fn write(&self, event: &dyn EventFields) {
+        let msg = match self.log_formatter.fields_msg(event) {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("Invalid log format: {}", e);
+                self.stats.format_error();
+                self.log_formatter.default_fields_msg(event)
+            },
+        };
+
+        let sent = match self.gelf {
+            true => self.send_gelf(msg),
+            false => self.send_text(msg),
+        };
+        match sent {
+            Some(bytes) => self.stats.written(event.level(), bytes),
+            None => self.stats.dropped(1),
+        }
+    }
+
+    // Sends a record in one or more datagrams, returning the bytes
+    // sent if every datagram was sent
+    fn send_text(&self, msg: String) -> Option<usize> {
+        if msg.len() <= self.mtu {
+            return self.send(msg.as_bytes());
+        }
+
+        match self.oversize {
+            Oversize::Truncate => {
+                // The marker is itself cut short if the mtu is smaller
+                let marker = &self.marker[..floor_char_boundary(&self.marker, self.mtu)];
+                let keep = floor_char_boundary(&msg, self.mtu - marker.len());
+                let mut datagram = String::with_capacity(self.mtu);
+                datagram.push_str(&msg[..keep]);
+                datagram.push_str(marker);
+                self.send(datagram.as_bytes())
+            },
+            Oversize::Split => {
+                let mut rest = msg.as_str();
+                let mut sent = Some(0);
+                while !rest.is_empty() {
+                    // Always make progress, even if the mtu is
+                    // smaller than a multibyte char
+                    let mut end = floor_char_boundary(rest, self.mtu);
+                    if end == 0 {
+                        end = rest.chars().next().map(|c| c.len_utf8()).unwrap_or(rest.len());
+                    }
+                    sent = add(sent, self.send(&rest.as_bytes()[..end]));
+                    rest = &rest[end..];
+                }
+                sent
+            },
+        }
+    }
+
+    // Sends a GELF record, compressed and chunked as needed
+    fn send_gelf(&self, msg: String) -> Option<usize> {
+        let payload = match compress(self.compression, msg) {
+            Ok(payload) => payload,
+            Err(_) => {
+                self.stats.write_error();
+                return None;
+            },
+        };
+        if payload.len() <= self.mtu {
+            return self.send(&payload);
+        }
+
+        // Each chunk and its header must fit in the mtu
+        let chunk_size = self.mtu.saturating_sub(GELF_CHUNK_HEADER);
+        if chunk_size == 0 {
+            self.stats.write_error();
+            return None;
+        }
+        let count = payload.len().div_ceil(chunk_size);
+        if count > GELF_MAX_CHUNKS {
+            self.stats.write_error();
+            return None;
+        }
+
+        let id = message_id();
+        let mut sent = Some(0);
+        for (seq, chunk) in payload.chunks(chunk_size).enumerate() {
+            let mut datagram = Vec::with_capacity(GELF_CHUNK_HEADER + chunk.len());
+            datagram.extend_from_slice(&[0x1e, 0x0f]);
+            datagram.extend_from_slice(&id);
+            datagram.push(seq as u8);
+            datagram.push(count as u8);
+            datagram.extend_from_slice(chunk);
+            sent = add(sent, self.send(&datagram));
+        }
+        sent
+    }
+}
+
+// Adds up the bytes sent, which are None once any send has failed
+fn add(total: Option<usize>, sent: Option<usize>) -> Option<usize> {
+    Some(total? + sent?)
+}
+
+fn compress(compression: GelfCompression, msg: String) -> io::Result<Vec<u8>> {
+    match compression {
+        GelfCompression::None => Ok(msg.into_bytes()),
+        GelfCompression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(msg.as_bytes())?;
+            encoder.finish()
+        },
+        GelfCompression::Zlib => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(msg.as_bytes())?;
+            encoder.finish()
+        },
+    }
+}
+
+// A GELF message id, which only needs to be unique among the
+// messages a collector is reassembling at the same time
+fn message_id() -> [u8; 8] {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(nanos);
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u32(std::process::id());
+    hasher.finish().to_be_bytes()
+}
+
+// Returns the largest index <= max which is on a char boundary
+fn floor_char_boundary(s: &str, max: usize) -> usize {
+    if max >= s.len() {
+        return s.len();
+    }
+    (0..=max).rev().find(|&i| s.is_char_boundary(i)).unwrap_or(0)
+}
+
+impl log::Log for UdpLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level_filter
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            self.stats.filtered();
+            return;
+        }
+
+        let _in_logger = sys::enter_logger();
+        self.write(&RecordEvent::new(record));
+    }
+
+    fn flush(&self) {}
+}
+
+impl CountedLog for UdpLogger {
+    fn stats(&self) -> Arc<LoggerStats> {
+        self.stats.clone()
+    }
+}